@@ -1,52 +1,818 @@
-use fundsp::prelude::{clamp01, lerp, lfo_in, shared, var, An, Atomic, EnvelopeIn, Frame, U5};
+use crate::param::Param;
+use crate::synth_params::{AdsrParams, DxEnvParams};
+use fundsp::math::{amp_db, db_amp};
+use fundsp::prelude::{clamp, clamp01, lerp, An, AudioNode, Frame, Shared, U1};
 use fundsp::Float;
 
-pub fn adsr(
-) -> An<EnvelopeIn<f32, impl FnMut(f32, &Frame<f32, U5>) -> f32 + Sized + Clone, U5, f32>> {
-    let neg1 = -1.0;
-    let zero = 0.0;
-    let a = shared(neg1);
-    let b = shared(neg1);
-    let attack_start = var(&a);
-    let release_start = var(&b);
-    lfo_in(move |time, control| {
-        let attack = control[0];
-        let decay = control[1];
-        let sustain = control[2];
-        let release = control[3];
-        let control = control[4];
-        if attack_start.value() < zero && control > zero {
-            attack_start.set_value(time);
-            release_start.set_value(neg1);
-        } else if release_start.value() < zero && control <= zero {
-            release_start.set_value(time);
-            attack_start.set_value(neg1);
-        }
-        clamp01(if release_start.value() < zero {
-            ads(attack, decay, sustain, time - attack_start.value())
+/// Steepness of a segment at curve amount 1.
+const CURVE_STEEPNESS: f32 = 6.0;
+/// Highest DX rate and level.
+const DX_MAX: f32 = 99.0;
+/// Attenuation per DX level below the maximum.
+const DX_LEVEL_DB: f32 = 0.75;
+/// Seconds rate 0 takes to sweep from level 0 to 99.
+const DX_SLOWEST_SWEEP: f32 = 55.0;
+/// Halvings of the sweep time per rate step, rate 99 sweeps in about a millisecond.
+const DX_RATE_HALVINGS: f32 = 0.16;
+
+/// What a new note does to an envelope whose gate is already open.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EnvMode {
+    /// Restart the attack from the current level.
+    Retrigger,
+    /// Keep running, only a closed gate restarts the attack.
+    Legato,
+}
+
+impl EnvMode {
+    pub fn from_value(value: f32) -> Self {
+        if value < 0.5 {
+            EnvMode::Retrigger
         } else {
-            releasing(sustain, release, time - release_start.value())
-        })
-    })
+            EnvMode::Legato
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EnvMode::Retrigger => "Retrig",
+            EnvMode::Legato => "Legato",
+        }
+    }
+}
+
+/// Envelope generator used by an operator.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EnvKind {
+    Adsr,
+    /// ADSR with a delay before the attack and a hold at full level after it.
+    Dahdsr,
+    /// Four rates and four levels as on the DX7.
+    Dx,
+}
+
+impl EnvKind {
+    pub fn from_value(value: f32) -> Self {
+        match value.round() as i32 {
+            i32::MIN..=0 => EnvKind::Adsr,
+            1 => EnvKind::Dahdsr,
+            _ => EnvKind::Dx,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EnvKind::Adsr => "ADSR",
+            EnvKind::Dahdsr => "DAHDSR",
+            EnvKind::Dx => "DX",
+        }
+    }
+}
+
+/// How the held part of an envelope repeats while the key is down.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoopMode {
+    Off,
+    /// Jump back to the loop start. A loop starting after the first segment ramps back
+    /// to the level at its start over the attack time, so the wrap never clicks.
+    Forward,
+    /// Run the loop range forwards and backwards.
+    PingPong,
+}
+
+impl LoopMode {
+    pub fn from_value(value: f32) -> Self {
+        match value.round() as i32 {
+            i32::MIN..=0 => LoopMode::Off,
+            1 => LoopMode::Forward,
+            _ => LoopMode::PingPong,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LoopMode::Off => "Off",
+            LoopMode::Forward => "Loop",
+            LoopMode::PingPong => "PingPong",
+        }
+    }
+}
+
+pub fn adsr(
+    params: &AdsrParams,
+    dx_params: &DxEnvParams,
+    kind: &Param,
+    gate: &Shared,
+    trigger: &Shared,
+    voice: usize,
+) -> An<Adsr> {
+    An(Adsr::new(params, dx_params, kind, gate, trigger, voice))
+}
+
+/// Ramp from the level where a forward loop wrapped to the level at the loop start.
+#[derive(Copy, Clone)]
+struct LoopReturn {
+    from: f32,
+    to: f32,
+    start: f32,
+    end: f32,
+}
+
+/// Envelope which starts every segment from its current level, so
+/// releasing or retriggering a note never jumps.
+/// The gate is open while `gate` is positive, every change of `trigger` is a new note.
+/// The shape is picked by `kind`, see [`EnvKind`]. Parameters follow the modulation of `voice`.
+/// - Input 0: time scale applied to every segment.
+/// - Output 0: envelope level.
+#[derive(Clone)]
+pub struct Adsr {
+    params: AdsrParams,
+    dx_params: DxEnvParams,
+    kind: Param,
+    gate: Shared,
+    trigger: Shared,
+    voice: usize,
+    sample_duration: f32,
+    held: bool,
+    last_trigger: f32,
+    time: f32,
+    /// 1 or -1, only runs backwards inside a ping-pong loop
+    direction: f32,
+    start_level: f32,
+    level: f32,
+    loop_return: Option<LoopReturn>,
+}
+
+impl Adsr {
+    pub fn new(
+        params: &AdsrParams,
+        dx_params: &DxEnvParams,
+        kind: &Param,
+        gate: &Shared,
+        trigger: &Shared,
+        voice: usize,
+    ) -> Self {
+        let mut adsr = Self {
+            params: params.clone(),
+            dx_params: dx_params.clone(),
+            kind: kind.clone(),
+            gate: gate.clone(),
+            trigger: trigger.clone(),
+            voice,
+            sample_duration: 0.0,
+            held: false,
+            last_trigger: 0.0,
+            time: 0.0,
+            direction: 1.0,
+            start_level: 0.0,
+            level: 0.0,
+            loop_return: None,
+        };
+        adsr.reset();
+        adsr.set_sample_rate(fundsp::DEFAULT_SR);
+        adsr
+    }
+
+    fn value(&self, param: &Param) -> f32 {
+        param.voice_value(self.voice)
+    }
+
+    fn start_segment(&mut self, held: bool) {
+        self.held = held;
+        self.time = 0.0;
+        self.direction = 1.0;
+        self.start_level = self.level;
+        self.loop_return = None;
+    }
+
+    /// Durations of the segments played while the key is held and how many there are.
+    fn held_segments(&self, kind: EnvKind, scale: f32) -> ([f32; 4], usize) {
+        let params = &self.params;
+        let dx = &self.dx_params;
+        let (segments, count) = match kind {
+            EnvKind::Adsr => ([self.value(&params.a), self.value(&params.d), 0.0, 0.0], 2),
+            EnvKind::Dahdsr => (
+                [
+                    self.value(&params.delay),
+                    self.value(&params.a),
+                    self.value(&params.hold),
+                    self.value(&params.d),
+                ],
+                4,
+            ),
+            EnvKind::Dx => {
+                let start = dx_level(self.start_level);
+                let [l1, l2, l3] = [&dx.l1, &dx.l2, &dx.l3].map(|l| self.value(l));
+                (
+                    [
+                        dx_segment_time(self.value(&dx.r1), start, l1),
+                        dx_segment_time(self.value(&dx.r2), l1, l2),
+                        dx_segment_time(self.value(&dx.r3), l2, l3),
+                        0.0,
+                    ],
+                    3,
+                )
+            }
+        };
+        (segments.map(|segment| segment * scale), count)
+    }
+
+    /// Start and end time of the looped segment range.
+    fn loop_range(&self, kind: EnvKind, scale: f32) -> (f32, f32) {
+        let (segments, count) = self.held_segments(kind, scale);
+        let last = count - 1;
+        let start = (self.value(&self.params.loop_start) as usize).min(last);
+        let end = (self.value(&self.params.loop_end) as usize).clamp(start, last);
+        (
+            segments[..start].iter().sum(),
+            segments[..=end].iter().sum(),
+        )
+    }
+
+    fn apply_loop(&mut self, kind: EnvKind, scale: f32) {
+        let mode = LoopMode::from_value(self.value(&self.params.loop_mode));
+        if mode == LoopMode::Off {
+            return;
+        }
+        let (begin, end) = self.loop_range(kind, scale);
+        if end - begin <= self.sample_duration {
+            return;
+        }
+        match mode {
+            LoopMode::Forward if self.time >= end && begin <= 0.0 => {
+                // the first segment already starts from the current level
+                self.time = 0.0;
+                self.start_level = self.level;
+            }
+            LoopMode::Forward if self.time >= end => {
+                self.time = begin;
+                let to = self.shape(kind, scale);
+                let duration = self.return_time(kind, scale, to);
+                self.loop_return = Some(LoopReturn {
+                    from: self.level,
+                    to,
+                    start: begin - duration,
+                    end: begin,
+                });
+                self.time = begin - duration;
+            }
+            LoopMode::PingPong if self.time >= end => {
+                self.time = end;
+                self.direction = -1.0;
+            }
+            LoopMode::PingPong if self.time <= begin && self.direction < 0.0 => {
+                self.time = begin;
+                self.direction = 1.0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Time to ramp back to the level `to` after a forward loop, the attack or the
+    /// first DX segment.
+    fn return_time(&self, kind: EnvKind, scale: f32, to: f32) -> f32 {
+        match kind {
+            EnvKind::Dx => {
+                let rate = self.value(&self.dx_params.r1);
+                dx_segment_time(rate, dx_level(self.level), dx_level(to)) * scale
+            }
+            _ => self.value(&self.params.a) * scale,
+        }
+    }
+
+    /// Level at the current time of the segment being played.
+    fn shape(&self, kind: EnvKind, scale: f32) -> f32 {
+        let params = &self.params;
+        let dx = &self.dx_params;
+        match (kind, self.held) {
+            (EnvKind::Dx, true) => dx_amplitude(dx_held(
+                dx_level(self.start_level),
+                [&dx.r1, &dx.r2, &dx.r3].map(|r| self.value(r)),
+                [&dx.l1, &dx.l2, &dx.l3].map(|l| self.value(l)),
+                scale,
+                self.time,
+            )),
+            (EnvKind::Dx, false) => dx_amplitude(dx_releasing(
+                dx_level(self.start_level),
+                self.value(&dx.r4),
+                self.value(&dx.l4),
+                scale,
+                self.time,
+            )),
+            (_, true) => {
+                let (delay, hold) = match kind {
+                    EnvKind::Dahdsr => (self.value(&params.delay), self.value(&params.hold)),
+                    _ => (0.0, 0.0),
+                };
+                dahds(
+                    self.start_level,
+                    delay * scale,
+                    self.value(&params.a) * scale,
+                    hold * scale,
+                    self.value(&params.d) * scale,
+                    self.value(&params.s),
+                    self.value(&params.a_curve),
+                    self.value(&params.d_curve),
+                    self.time,
+                )
+            }
+            (_, false) => releasing(
+                self.start_level,
+                self.value(&params.r) * scale,
+                self.value(&params.r_curve),
+                self.time,
+            ),
+        }
+    }
+}
+
+impl AudioNode for Adsr {
+    const ID: u64 = 1341;
+    type Inputs = U1;
+    type Outputs = U1;
+
+    fn reset(&mut self) {
+        self.held = false;
+        self.last_trigger = self.trigger.value();
+        self.time = 0.0;
+        self.direction = 1.0;
+        self.start_level = 0.0;
+        self.level = 0.0;
+        self.loop_return = None;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_duration = (1.0 / sample_rate) as f32;
+    }
+
+    #[inline]
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let gate = self.gate.value() > 0.0;
+        let trigger = self.trigger.value();
+        let retriggered = trigger != self.last_trigger;
+        self.last_trigger = trigger;
+        let mode = EnvMode::from_value(self.value(&self.params.mode));
+
+        if gate && (!self.held || (retriggered && mode == EnvMode::Retrigger)) {
+            self.start_segment(true);
+        } else if !gate && self.held {
+            self.start_segment(false);
+        }
+
+        let scale = input[0];
+        let kind = EnvKind::from_value(self.value(&self.kind));
+        self.level = clamp01(match self.loop_return.filter(|ramp| self.time < ramp.end) {
+            Some(ramp) => lerp(
+                ramp.from,
+                ramp.to,
+                (self.time - ramp.start) / (ramp.end - ramp.start),
+            ),
+            _ => {
+                self.loop_return = None;
+                self.shape(kind, scale)
+            }
+        });
+        self.time += self.sample_duration * self.direction;
+        if self.held {
+            self.apply_loop(kind, scale);
+        }
+        [self.level].into()
+    }
+}
+
+/// Bends the position inside a segment. Positive curves are exponential and
+/// start slowly, negative curves are logarithmic and start fast, 0 is linear.
+pub fn curve<F: Float>(position: F, curve: F) -> F {
+    let k = clamp(-1.0, 1.0, curve.to_f32()) * CURVE_STEEPNESS;
+    if k.abs() < 1e-3 {
+        position
+    } else {
+        F::from_f64((((k * position.to_f32()).exp() - 1.0) / (k.exp() - 1.0)) as f64)
+    }
 }
 
-fn ads<F: Float>(attack: F, decay: F, sustain: F, time: F) -> F {
+fn ads<F: Float>(
+    start_level: F,
+    attack: F,
+    decay: F,
+    sustain: F,
+    attack_curve: F,
+    decay_curve: F,
+    time: F,
+) -> F {
     if time.to_f32() < attack.to_f32() {
-        lerp(F::from_f64(0.0), F::from_f64(1.0), time / attack)
+        lerp(
+            start_level,
+            F::from_f64(1.0),
+            curve(time / attack, attack_curve),
+        )
     } else {
         let decay_time = time - attack;
         if decay_time.to_f32() < decay.to_f32() {
-            lerp(F::from_f64(1.0), sustain, decay_time / decay)
+            lerp(
+                F::from_f64(1.0),
+                sustain,
+                curve(decay_time / decay, decay_curve),
+            )
         } else {
             sustain
         }
     }
 }
 
-fn releasing<F: Float>(sustain: F, release: F, release_time: F) -> F {
-    if release_time.to_f32() > release.to_f32() {
+/// [`ads`] with a delay at the starting level before the attack and a hold at
+/// full level after it.
+fn dahds<F: Float>(
+    start_level: F,
+    delay: F,
+    attack: F,
+    hold: F,
+    decay: F,
+    sustain: F,
+    attack_curve: F,
+    decay_curve: F,
+    time: F,
+) -> F {
+    let time = time - delay;
+    if time.to_f32() < 0.0 {
+        start_level
+    } else if time.to_f32() < attack.to_f32() {
+        ads(start_level, attack, decay, sustain, attack_curve, decay_curve, time)
+    } else if time.to_f32() < (attack + hold).to_f32() {
+        F::from_f64(1.0)
+    } else {
+        ads(
+            start_level,
+            attack,
+            decay,
+            sustain,
+            attack_curve,
+            decay_curve,
+            time - hold,
+        )
+    }
+}
+
+/// Amplitude of a DX level, every level below 99 is 0.75 dB quieter and 0 is silent.
+pub fn dx_amplitude(level: f32) -> f32 {
+    if level <= 0.0 {
+        0.0
+    } else {
+        db_amp((level.min(DX_MAX) - DX_MAX) * DX_LEVEL_DB)
+    }
+}
+
+/// DX level of an amplitude, the inverse of [`dx_amplitude`].
+pub fn dx_level(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        0.0
+    } else {
+        clamp(0.0, DX_MAX, DX_MAX + amp_db(amplitude) / DX_LEVEL_DB)
+    }
+}
+
+/// Seconds a segment at a DX rate takes to move between two levels.
+/// Levels move at a constant speed set by the rate, from about 55 seconds for the whole
+/// range at rate 0 to about a millisecond at rate 99. This approximates the DX7, whose
+/// real timing also differs between rising and falling segments.
+pub fn dx_segment_time(rate: f32, from: f32, to: f32) -> f32 {
+    let sweep = DX_SLOWEST_SWEEP * (-rate * DX_RATE_HALVINGS).exp2();
+    sweep * (to - from).abs() / DX_MAX
+}
+
+/// Moves through L1..L3 at R1..R3 and stays at L3, in DX levels.
+/// Segment times are multiplied by `scale`.
+fn dx_held(start_level: f32, rates: [f32; 3], levels: [f32; 3], scale: f32, time: f32) -> f32 {
+    let mut from = start_level;
+    let mut time = time;
+    for (rate, level) in rates.into_iter().zip(levels) {
+        let duration = dx_segment_time(rate, from, level) * scale;
+        if time < duration {
+            return lerp(from, level, time / duration);
+        }
+        time -= duration;
+        from = level;
+    }
+    from
+}
+
+fn dx_releasing(start_level: f32, rate: f32, level: f32, scale: f32, time: f32) -> f32 {
+    let duration = dx_segment_time(rate, start_level, level) * scale;
+    if time >= duration {
+        level
+    } else {
+        lerp(start_level, level, time / duration)
+    }
+}
+
+fn releasing<F: Float>(start_level: F, release: F, release_curve: F, release_time: F) -> F {
+    if release_time.to_f32() >= release.to_f32() {
         F::from_f64(0.0)
     } else {
-        lerp(sustain, F::from_f64(0.0), release_time / release)
+        lerp(
+            start_level,
+            F::from_f64(0.0),
+            curve(release_time / release, release_curve),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!(
+            (value - expected).abs() < 1e-4,
+            "expected {expected}, got {value}"
+        );
+    }
+
+    #[test]
+    fn linear_segments() {
+        assert_close(ads(0.0, 1.0, 1.0, 0.5, 0.0, 0.0, 0.25), 0.25);
+        assert_close(ads(0.0, 1.0, 1.0, 0.5, 0.0, 0.0, 1.5), 0.75);
+        assert_close(ads(0.0, 1.0, 1.0, 0.5, 0.0, 0.0, 3.0), 0.5);
+        assert_close(releasing(0.5, 1.0, 0.0, 0.5), 0.25);
+        assert_close(releasing(0.5, 1.0, 0.0, 2.0), 0.0);
+    }
+
+    #[test]
+    fn dahds_segments() {
+        assert_close(dahds(0.0, 1.0, 1.0, 1.0, 1.0, 0.5, 0.0, 0.0, 0.5), 0.0);
+        assert_close(dahds(0.0, 1.0, 1.0, 1.0, 1.0, 0.5, 0.0, 0.0, 1.5), 0.5);
+        assert_close(dahds(0.0, 1.0, 1.0, 1.0, 1.0, 0.5, 0.0, 0.0, 2.5), 1.0);
+        assert_close(dahds(0.0, 1.0, 1.0, 1.0, 1.0, 0.5, 0.0, 0.0, 3.5), 0.75);
+        assert_close(dahds(0.0, 1.0, 1.0, 1.0, 1.0, 0.5, 0.0, 0.0, 5.0), 0.5);
+    }
+
+    #[test]
+    fn dx_levels() {
+        assert_eq!(dx_amplitude(99.0), 1.0);
+        assert_eq!(dx_amplitude(0.0), 0.0);
+        assert!((dx_amplitude(91.0) - db_amp(-6.0)).abs() < 1e-6);
+        assert_eq!(dx_level(0.0), 0.0);
+        for level in [1.0, 30.0, 75.5, 99.0] {
+            assert!((dx_level(dx_amplitude(level)) - level).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn dx_rates() {
+        assert!((dx_segment_time(0.0, 0.0, 99.0) - DX_SLOWEST_SWEEP).abs() < 1e-3);
+        let fastest = dx_segment_time(99.0, 99.0, 0.0);
+        assert!(fastest > 0.0005 && fastest < 0.002);
+        // higher rates are faster and time follows the distance between levels
+        assert!(dx_segment_time(50.0, 0.0, 99.0) < dx_segment_time(49.0, 0.0, 99.0));
+        let full = dx_segment_time(60.0, 0.0, 99.0);
+        assert!((dx_segment_time(60.0, 90.0, 57.0) - full / 3.0).abs() < 1e-6);
+        assert_eq!(dx_segment_time(60.0, 40.0, 40.0), 0.0);
+    }
+
+    #[test]
+    fn dx_segments() {
+        let rates = [60.0, 50.0, 40.0];
+        let levels = [99.0, 66.0, 80.0];
+        let times = [
+            dx_segment_time(60.0, 0.0, 99.0),
+            dx_segment_time(50.0, 99.0, 66.0),
+            dx_segment_time(40.0, 66.0, 80.0),
+        ];
+        let held = |time| dx_held(0.0, rates, levels, 1.0, time);
+        assert_close(held(times[0] / 2.0), 49.5);
+        assert_close(held(times[0] + times[1] / 2.0), 82.5);
+        assert_close(held(times[0] + times[1] + times[2] / 2.0), 73.0);
+        assert_close(held(100.0), 80.0);
+        // scaled segments take longer
+        assert_close(dx_held(0.0, rates, levels, 2.0, times[0]), 49.5);
+
+        let release = dx_segment_time(70.0, 80.0, 10.0);
+        assert_close(dx_releasing(80.0, 70.0, 10.0, 1.0, release / 2.0), 45.0);
+        assert_close(dx_releasing(80.0, 70.0, 10.0, 1.0, release * 2.0), 10.0);
+    }
+
+    #[test]
+    fn curve_shapes() {
+        assert_close(curve(0.5, 0.0), 0.5);
+        assert!(curve(0.5, 1.0) < 0.5);
+        assert!(curve(0.5, -1.0) > 0.5);
+        for amount in [-1.0, -0.3, 0.3, 1.0] {
+            assert_close(curve(0.0, amount), 0.0);
+            assert_close(curve(1.0, amount), 1.0);
+        }
+    }
+
+    #[test]
+    fn exponential_attack_starts_slow() {
+        let linear = ads(0.0, 1.0, 1.0, 0.5, 0.0, 0.0, 0.5);
+        let exponential = ads(0.0, 1.0, 1.0, 0.5, 1.0, 0.0, 0.5);
+        let logarithmic = ads(0.0, 1.0, 1.0, 0.5, -1.0, 0.0, 0.5);
+        assert!(exponential < linear);
+        assert!(logarithmic > linear);
+        let k = CURVE_STEEPNESS;
+        assert_close(exponential, ((k * 0.5).exp() - 1.0) / (k.exp() - 1.0));
+    }
+
+    #[test]
+    fn logarithmic_decay_and_release_drop_fast() {
+        let linear_decay = ads(0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.25);
+        let log_decay = ads(0.0, 1.0, 1.0, 0.0, 0.0, -1.0, 1.25);
+        assert_close(linear_decay, 0.75);
+        assert!(log_decay < linear_decay);
+
+        let linear_release = releasing(1.0, 1.0, 0.0, 0.25);
+        let log_release = releasing(1.0, 1.0, -1.0, 0.25);
+        let exp_release = releasing(1.0, 1.0, 1.0, 0.25);
+        assert!(log_release < linear_release);
+        assert!(exp_release > linear_release);
+    }
+
+    struct TestEnvelope {
+        adsr: Adsr,
+        params: AdsrParams,
+        gate: Shared,
+        trigger: Shared,
+    }
+
+    impl TestEnvelope {
+        /// 1 kHz sample rate so one tick is one millisecond.
+        fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+            let params = AdsrParams::default();
+            params.a.set_value(attack);
+            params.d.set_value(decay);
+            params.s.set_value(sustain);
+            params.r.set_value(release);
+            let gate = Shared::new(0.0);
+            let trigger = Shared::new(0.0);
+            let kind = Param::new(0.0, (0.0, 2.0), None);
+            let mut adsr = Adsr::new(&params, &DxEnvParams::default(), &kind, &gate, &trigger, 0);
+            adsr.set_sample_rate(1000.0);
+            Self {
+                adsr,
+                params,
+                gate,
+                trigger,
+            }
+        }
+
+        fn note_on(&self) {
+            self.trigger.set_value(self.trigger.value() + 1.0);
+            self.gate.set_value(1.0);
+        }
+
+        fn note_off(&self) {
+            self.gate.set_value(-1.0);
+        }
+
+        fn run(&mut self, ticks: usize) -> f32 {
+            let mut level = 0.0;
+            for _ in 0..ticks {
+                level = self.adsr.tick(&[1.0].into())[0];
+            }
+            level
+        }
+    }
+
+    #[test]
+    fn note_off_during_attack_releases_from_current_level() {
+        let mut env = TestEnvelope::new(0.1, 0.1, 1.0, 0.1);
+        env.note_on();
+        assert_close(env.run(51), 0.5);
+
+        env.note_off();
+        assert_close(env.run(1), 0.5);
+        assert_close(env.run(50), 0.25);
+        assert_close(env.run(50), 0.0);
+    }
+
+    #[test]
+    fn note_off_during_decay_releases_from_current_level() {
+        let mut env = TestEnvelope::new(0.0, 0.1, 0.0, 0.1);
+        env.note_on();
+        // the attack is clamped to its shortest time of one tick
+        assert_close(env.run(77), 0.25);
+
+        env.note_off();
+        assert_close(env.run(1), 0.25);
+        assert!(env.run(1) < 0.25);
+    }
+
+    #[test]
+    fn curved_attack_follows_the_curve() {
+        for amount in [1.0, -1.0] {
+            let mut env = TestEnvelope::new(0.1, 0.1, 0.5, 0.1);
+            env.params.a_curve.set_value(amount);
+            env.note_on();
+            assert_close(env.run(26), curve(0.25, amount));
+            assert_close(env.run(25), curve(0.5, amount));
+            assert_close(env.run(25), curve(0.75, amount));
+        }
+    }
+
+    #[test]
+    fn curved_decay_and_release_follow_the_curve() {
+        let mut env = TestEnvelope::new(0.1, 0.1, 0.5, 0.2);
+        env.params.d_curve.set_value(-1.0);
+        env.params.r_curve.set_value(1.0);
+        env.note_on();
+        assert_close(env.run(151), lerp(1.0, 0.5, curve(0.5, -1.0)));
+        assert_close(env.run(100), 0.5);
+
+        env.note_off();
+        assert_close(env.run(1), 0.5);
+        assert_close(env.run(50), lerp(0.5, 0.0, curve(0.25, 1.0)));
+        assert_close(env.run(100), lerp(0.5, 0.0, curve(0.75, 1.0)));
+    }
+
+    #[test]
+    fn retrigger_during_release_starts_from_current_level() {
+        let mut env = TestEnvelope::new(0.1, 0.0, 1.0, 0.1);
+        env.note_on();
+        env.run(200);
+        env.note_off();
+        assert_close(env.run(51), 0.5);
+
+        env.note_on();
+        assert_close(env.run(1), 0.5);
+        assert_close(env.run(50), 0.75);
+    }
+
+    #[test]
+    fn retrigger_mode_restarts_attack_while_held() {
+        let mut env = TestEnvelope::new(0.1, 0.0, 0.5, 0.1);
+        env.note_on();
+        assert_close(env.run(200), 0.5);
+
+        env.note_on();
+        assert_close(env.run(1), 0.5);
+        assert_close(env.run(50), 0.75);
+    }
+
+    #[test]
+    fn forward_loop_repeats_attack_and_decay() {
+        let mut env = TestEnvelope::new(0.1, 0.1, 0.0, 0.1);
+        env.params.loop_mode.set_value(1.0);
+        env.note_on();
+        assert_close(env.run(51), 0.5);
+        assert_close(env.run(50), 1.0);
+        assert!(env.run(99) < 0.05);
+        assert!(env.run(50) > 0.45);
+    }
+
+    #[test]
+    fn forward_loop_from_decay_ramps_back_to_the_peak() {
+        let mut env = TestEnvelope::new(0.1, 0.1, 0.0, 0.1);
+        env.params.loop_mode.set_value(1.0);
+        env.params.loop_start.set_value(1.0);
+        env.note_on();
+        let mut last = env.run(150);
+        let mut peak = 0.0f32;
+        for tick in 150..450 {
+            let level = env.run(1);
+            // attack, decay and the ramp back all move 0.01 per tick
+            assert!(
+                (level - last).abs() < 0.0105,
+                "jumped from {last} to {level} at tick {tick}"
+            );
+            if tick > 250 {
+                peak = peak.max(level);
+            }
+            last = level;
+        }
+        assert!(peak > 0.95);
+    }
+
+    #[test]
+    fn ping_pong_loop_runs_back_through_decay() {
+        let mut env = TestEnvelope::new(0.1, 0.1, 0.0, 0.1);
+        env.params.loop_mode.set_value(2.0);
+        env.params.loop_start.set_value(1.0);
+        env.note_on();
+        assert!(env.run(200) < 0.05);
+        assert!((env.run(50) - 0.5).abs() < 0.05);
+        assert!(env.run(50) > 0.95);
+        assert!((env.run(50) - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn loop_stops_on_release() {
+        let mut env = TestEnvelope::new(0.1, 0.1, 0.0, 0.1);
+        env.params.loop_mode.set_value(1.0);
+        env.note_on();
+        env.run(250);
+        env.note_off();
+        env.run(100);
+        assert_close(env.run(100), 0.0);
+    }
+
+    #[test]
+    fn legato_mode_ignores_retrigger_while_held() {
+        let mut env = TestEnvelope::new(0.1, 0.0, 0.5, 0.1);
+        env.params.mode.set_value(1.0);
+        env.note_on();
+        assert_close(env.run(200), 0.5);
+
+        env.note_on();
+        assert_close(env.run(50), 0.5);
     }
 }
@@ -0,0 +1,70 @@
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+/// Operator routing of a voice.
+/// Operators are indexed from 0, the diagrams below use 1-based numbers
+/// the same way the Op pages do.
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
+pub enum Algorithm {
+    /// 4 → 3 → 2 → 1
+    Stack,
+    /// (3 + 4) → 2 → 1
+    BranchStack,
+    /// (2 + 3 + 4) → 1
+    ThreeToOne,
+    /// 4 → 3, 2 → 1
+    Pairs,
+    /// 4 → 3 → 2, 1
+    StackAndCarrier,
+    /// 4 → (1, 2, 3)
+    OneToThree,
+    /// 1, 2, 3, 4
+    Additive,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Stack => "Stack",
+            Algorithm::BranchStack => "Branch",
+            Algorithm::ThreeToOne => "3 > 1",
+            Algorithm::Pairs => "Pairs",
+            Algorithm::StackAndCarrier => "Stack + 1",
+            Algorithm::OneToThree => "1 > 3",
+            Algorithm::Additive => "Additive",
+        }
+    }
+
+    /// Modulation connections as `(modulator, target)` operator indices.
+    pub fn connections(&self) -> &'static [(usize, usize)] {
+        match self {
+            Algorithm::Stack => &[(3, 2), (2, 1), (1, 0)],
+            Algorithm::BranchStack => &[(3, 1), (2, 1), (1, 0)],
+            Algorithm::ThreeToOne => &[(3, 0), (2, 0), (1, 0)],
+            Algorithm::Pairs => &[(3, 2), (1, 0)],
+            Algorithm::StackAndCarrier => &[(3, 2), (2, 1)],
+            Algorithm::OneToThree => &[(3, 2), (3, 1), (3, 0)],
+            Algorithm::Additive => &[],
+        }
+    }
+
+    /// Operators which are mixed into the voice output.
+    pub fn carriers(&self) -> &'static [usize] {
+        match self {
+            Algorithm::Stack | Algorithm::BranchStack | Algorithm::ThreeToOne => &[0],
+            Algorithm::Pairs => &[2, 0],
+            Algorithm::StackAndCarrier => &[1, 0],
+            Algorithm::OneToThree => &[2, 1, 0],
+            Algorithm::Additive => &[3, 2, 1, 0],
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        Algorithm::iter().position(|a| a == *self).unwrap_or(0)
+    }
+
+    pub fn from_index(index: usize) -> Self {
+        let count = Algorithm::iter().count();
+        Algorithm::iter().nth(index % count).unwrap()
+    }
+}
@@ -1,11 +1,13 @@
 use once_cell::sync::OnceCell;
 
+use crate::algorithm::Algorithm;
+use crate::modulation::ModDestinations;
 use crate::synth_params::SynthParams;
-use crate::ui::ui_state::{OpPage, Page, UIState};
+use crate::ui::ui_state::{ModPage, OpPage, Page, UIState};
 
 use skia_safe::{
     surfaces, AlphaType, Canvas, Color, ColorSpace, ColorType, Font, FontMgr, FontStyle, ImageInfo,
-    Paint, Point, Typeface,
+    Paint, PaintStyle, Point, Rect, Typeface,
 };
 
 pub fn default_typeface() -> Typeface {
@@ -42,13 +44,89 @@ pub fn render_param(name: &str, value: String, point: impl Into<Point>, canvas:
     );
 }
 
-fn fmt_float(f: f32) -> String {
-    format!("{:.2}", f)
+/// Draws operator boxes with carriers on the bottom row and every modulator
+/// one row above its target. `point` is the top left corner of the bottom row,
+/// rows move closer together when the deepest modulator would leave the display.
+pub fn render_algorithm(algorithm: Algorithm, point: impl Into<Point>, canvas: &Canvas) {
+    let Point { x, y } = point.into();
+    let size = 28.0;
+    let column_step = 48.0;
+    let margin = 4.0;
+
+    let mut line_paint = Paint::default();
+    line_paint.set_color(Color::WHITE);
+    line_paint.set_anti_alias(true);
+    line_paint.set_stroke_width(2.0);
+    line_paint.set_style(PaintStyle::Stroke);
+    let mut text_paint = Paint::default();
+    text_paint.set_color(Color::WHITE);
+    text_paint.set_anti_alias(true);
+    let font = Font::from_typeface(default_typeface(), 20.0);
+
+    let mut depth = [0usize; 4];
+    for _ in 0..depth.len() {
+        for (modulator, target) in algorithm.connections() {
+            depth[*modulator] = depth[*modulator].max(depth[*target] + 1);
+        }
+    }
+    let row_step = match depth.iter().max() {
+        Some(&max_depth) if max_depth > 0 => ((y - margin) / max_depth as f32).min(38.0),
+        _ => 38.0,
+    };
+    let top_left = |op: usize| {
+        let column = (op + 1..depth.len())
+            .filter(|other| depth[*other] == depth[op])
+            .count();
+        (
+            x + column as f32 * column_step,
+            y - depth[op] as f32 * row_step,
+        )
+    };
+
+    for op in 0..depth.len() {
+        let (op_x, op_y) = top_left(op);
+        canvas.draw_rect(Rect::from_xywh(op_x, op_y, size, size), &line_paint);
+        canvas.draw_str(
+            format!("{}", op + 1),
+            (op_x + 8.0, op_y + 21.0),
+            &font,
+            &text_paint,
+        );
+    }
+    for (modulator, target) in algorithm.connections() {
+        let (from_x, from_y) = top_left(*modulator);
+        let (to_x, to_y) = top_left(*target);
+        canvas.draw_line(
+            (from_x + size / 2.0, from_y + size),
+            (to_x + size / 2.0, to_y),
+            &line_paint,
+        );
+    }
+    let output_y = y + size + 8.0;
+    for carrier in algorithm.carriers() {
+        let (op_x, op_y) = top_left(*carrier);
+        canvas.draw_line(
+            (op_x + size / 2.0, op_y + size),
+            (op_x + size / 2.0, output_y),
+            &line_paint,
+        );
+    }
+    let last_column = algorithm
+        .carriers()
+        .iter()
+        .map(|carrier| top_left(*carrier).0)
+        .fold(x, f32::max);
+    canvas.draw_line(
+        (x + size / 2.0, output_y),
+        (last_column + size / 2.0, output_y),
+        &line_paint,
+    );
 }
 
 pub fn render_image(
     params: &SynthParams,
     state: UIState,
+    dests: &ModDestinations,
     pixels: &mut [u8; 2048 * 160],
 ) -> [u8; 2048 * 160] {
     let image_info = ImageInfo::new(
@@ -69,56 +147,139 @@ pub fn render_image(
 
     let page = state.page.lock().unwrap();
     let op_subpage = state.op_subpage.lock().unwrap();
-    let dest = state.lfo_dest.lock().unwrap();
+    let mod_subpage = state.mod_subpage.lock().unwrap();
+    let slot = state.mod_slot.lock().unwrap();
     let calc_param_pos = |ord: f32| (120. * ord - 120. / 2. - 40., 60.);
     match *page {
         Page::Op(x) => match *op_subpage {
             OpPage::Tone => {
-                render_param(
-                    "Volume",
-                    fmt_float(params.ops[x as usize].volume.value()),
-                    calc_param_pos(1.),
-                    canvas,
-                );
-                render_param(
-                    "Ratio",
-                    fmt_float(params.ops[x as usize].ratio.value()),
-                    calc_param_pos(2.),
-                    canvas,
-                );
+                let op = &params.ops[x as usize];
+                for (i, param) in op.tone_params().iter().enumerate() {
+                    render_param(
+                        param.name(),
+                        param.display(),
+                        calc_param_pos(i as f32 + 1.),
+                        canvas,
+                    );
+                }
             }
             OpPage::Amp => {
+                let adsr = &params.ops[x as usize].adsr_params;
+                for (i, param) in adsr.amp_params().iter().enumerate() {
+                    render_param(
+                        param.name(),
+                        param.display(),
+                        calc_param_pos(i as f32 + 1.),
+                        canvas,
+                    );
+                }
+            }
+            OpPage::Scaling => {
+                let op = &params.ops[x as usize];
+                for (i, param) in op.scaling_params().iter().enumerate() {
+                    render_param(
+                        param.name(),
+                        param.display(),
+                        calc_param_pos(i as f32 + 1.),
+                        canvas,
+                    );
+                }
+            }
+            OpPage::Env => {
+                let op = &params.ops[x as usize];
+                for (i, param) in op.env_params().iter().enumerate() {
+                    render_param(
+                        param.name(),
+                        param.display(),
+                        calc_param_pos(i as f32 + 1.),
+                        canvas,
+                    );
+                }
+            }
+            OpPage::Dx => {
+                let dx = &params.ops[x as usize].dx_params;
+                for (i, param) in dx.params().iter().enumerate() {
+                    render_param(
+                        param.name(),
+                        param.display(),
+                        calc_param_pos(i as f32 + 1.),
+                        canvas,
+                    );
+                }
+            }
+        },
+        Page::Modulation => match *mod_subpage {
+            ModPage::Matrix => {
+                let mod_slot = &params.mod_slots[*slot];
+                render_param("Slot", format!("{}", *slot + 1), calc_param_pos(1.), canvas);
                 render_param(
-                    "Attack",
-                    fmt_float(params.ops[x as usize].adsr_params.a.value()),
-                    calc_param_pos(1.),
-                    canvas,
-                );
-                render_param(
-                    "Decay",
-                    fmt_float(params.ops[x as usize].adsr_params.d.value()),
+                    "Source",
+                    mod_slot.source().name().to_string(),
                     calc_param_pos(2.),
                     canvas,
                 );
                 render_param(
-                    "Sustain",
-                    fmt_float(params.ops[x as usize].adsr_params.s.value()),
+                    "Dest",
+                    dests[mod_slot.destination()].1.name.clone(),
                     calc_param_pos(3.),
                     canvas,
                 );
+                for (ord, param) in [(4., &mod_slot.amount), (8., &params.tempo)] {
+                    render_param(param.name(), param.display(), calc_param_pos(ord), canvas);
+                }
+            }
+            ModPage::Lfo(index) => {
+                for (i, param) in params.lfos[index].page_params().iter().enumerate() {
+                    render_param(
+                        param.name(),
+                        param.display(),
+                        calc_param_pos(i as f32 + 1.),
+                        canvas,
+                    );
+                }
+            }
+        },
+        Page::Algorithm => {
+            let algorithm = params.algorithm();
+            render_param(
+                "Algorithm",
+                algorithm.name().to_string(),
+                calc_param_pos(1.),
+                canvas,
+            );
+            render_algorithm(algorithm, (400., 112.), canvas);
+        }
+        Page::Voice => {
+            let settings = &params.voice_settings;
+            render_param(
+                "Mode",
+                settings.mode.lock().unwrap().name().to_string(),
+                calc_param_pos(1.),
+                canvas,
+            );
+            render_param(
+                "Priority",
+                settings.priority.lock().unwrap().name().to_string(),
+                calc_param_pos(2.),
+                canvas,
+            );
+            let values = [
+                &settings.glide,
+                &settings.unison,
+                &settings.unison_detune,
+                &settings.unison_spread,
+                &settings.bend_up,
+                &settings.bend_down,
+            ];
+            for (i, param) in values.iter().enumerate() {
                 render_param(
-                    "Release",
-                    fmt_float(params.ops[x as usize].adsr_params.r.value()),
-                    calc_param_pos(4.),
+                    param.name(),
+                    param.display(),
+                    calc_param_pos(i as f32 + 3.),
                     canvas,
                 );
             }
-        },
-        Page::Modulation => {
-            let dest = dest.to_owned().1;
-            render_param("Op 2", dest.name, calc_param_pos(1.), canvas)
         }
-        _ => {}
     }
     canvas.scale((1.0, 1.0));
     canvas.save();
@@ -0,0 +1,115 @@
+use fundsp::prelude::{An, AudioNode, Frame, Shared, U0, U1};
+
+pub fn glide(target: &Shared, time: &Shared) -> An<Glide> {
+    An(Glide::new(target, time))
+}
+
+/// Portamento, slides towards the value of `target` in `time` seconds.
+/// The slide is exponential so a pitch in Hz moves linearly in semitones.
+/// - Output 0: current value.
+#[derive(Clone)]
+pub struct Glide {
+    target: Shared,
+    time: Shared,
+    sample_duration: f32,
+    last_target: f32,
+    current: f32,
+    ratio: f32,
+    remaining: u32,
+}
+
+impl Glide {
+    pub fn new(target: &Shared, time: &Shared) -> Self {
+        let mut glide = Self {
+            target: target.clone(),
+            time: time.clone(),
+            sample_duration: 0.0,
+            last_target: 0.0,
+            current: 0.0,
+            ratio: 1.0,
+            remaining: 0,
+        };
+        glide.reset();
+        glide.set_sample_rate(fundsp::DEFAULT_SR);
+        glide
+    }
+}
+
+impl AudioNode for Glide {
+    const ID: u64 = 1342;
+    type Inputs = U0;
+    type Outputs = U1;
+
+    fn reset(&mut self) {
+        self.last_target = self.target.value();
+        self.current = self.last_target;
+        self.ratio = 1.0;
+        self.remaining = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_duration = (1.0 / sample_rate) as f32;
+    }
+
+    #[inline]
+    fn tick(&mut self, _input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let target = self.target.value();
+        if target != self.last_target {
+            self.last_target = target;
+            let samples = (self.time.value() / self.sample_duration).round();
+            if samples < 1.0 || self.current <= 0.0 || target <= 0.0 {
+                self.current = target;
+                self.remaining = 0;
+            } else {
+                self.ratio = (target / self.current).powf(1.0 / samples);
+                self.remaining = samples as u32;
+            }
+        }
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                target
+            } else {
+                self.current * self.ratio
+            };
+        }
+        [self.current].into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fundsp::prelude::shared;
+
+    fn run(glide: &mut Glide, ticks: usize) -> f32 {
+        let mut value = 0.0;
+        for _ in 0..ticks {
+            value = glide.tick(&Frame::default())[0];
+        }
+        value
+    }
+
+    #[test]
+    fn slides_exponentially_to_the_target() {
+        let target = shared(110.0);
+        let time = shared(0.1);
+        let mut glide = Glide::new(&target, &time);
+        glide.set_sample_rate(1000.0);
+        assert_eq!(run(&mut glide, 1), 110.0);
+
+        target.set_value(440.0);
+        assert!((run(&mut glide, 50) - 220.0).abs() < 0.01);
+        assert_eq!(run(&mut glide, 50), 440.0);
+        assert_eq!(run(&mut glide, 10), 440.0);
+    }
+
+    #[test]
+    fn jumps_without_glide_time() {
+        let target = shared(110.0);
+        let time = shared(0.0);
+        let mut glide = Glide::new(&target, &time);
+        target.set_value(440.0);
+        assert_eq!(run(&mut glide, 1), 440.0);
+    }
+}
@@ -0,0 +1,73 @@
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+/// Level scaling curve for one side of the breakpoint, the same set as on the DX7.
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
+pub enum KeyCurve {
+    NegLin,
+    NegExp,
+    PosExp,
+    PosLin,
+}
+
+impl KeyCurve {
+    pub fn from_value(value: f32) -> Self {
+        KeyCurve::iter()
+            .nth(value.round().max(0.0) as usize)
+            .unwrap_or(KeyCurve::PosLin)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyCurve::NegLin => "-Lin",
+            KeyCurve::NegExp => "-Exp",
+            KeyCurve::PosExp => "+Exp",
+            KeyCurve::PosLin => "+Lin",
+        }
+    }
+
+    /// Level change in dB for a normalized distance from the breakpoint.
+    fn db(&self, distance: f32, depth: f32) -> f32 {
+        let exp = || ((3.0 * distance).exp() - 1.0) / (3.0_f32.exp() - 1.0);
+        match self {
+            KeyCurve::NegLin => -distance * depth * MAX_SCALING_DB,
+            KeyCurve::NegExp => -exp() * depth * MAX_SCALING_DB,
+            KeyCurve::PosExp => exp() * depth * MAX_SCALING_DB,
+            KeyCurve::PosLin => distance * depth * MAX_SCALING_DB,
+        }
+    }
+}
+
+const MAX_SCALING_DB: f32 = 48.0;
+/// Distance from the breakpoint at which the scaling reaches its full depth.
+const SCALING_RANGE_OCTAVES: f32 = 4.0;
+/// Note at which rate scaling leaves envelope times untouched.
+const RATE_SCALING_BASE_NOTE: f32 = 21.0;
+
+/// Gain factor applied to an operator for the played note.
+pub fn level_scale(
+    note: f32,
+    breakpoint: f32,
+    left_depth: f32,
+    right_depth: f32,
+    left_curve: KeyCurve,
+    right_curve: KeyCurve,
+) -> f32 {
+    let distance = ((note - breakpoint).abs() / 12.0 / SCALING_RANGE_OCTAVES).min(1.0);
+    let db = if note < breakpoint {
+        left_curve.db(distance, left_depth)
+    } else {
+        right_curve.db(distance, right_depth)
+    };
+    10.0_f32.powf(db / 20.0)
+}
+
+/// Gain factor for a velocity in 0..1, `sensitivity` 0 ignores velocity completely.
+pub fn velocity_scale(velocity: f32, sensitivity: f32) -> f32 {
+    1.0 - sensitivity + sensitivity * velocity
+}
+
+/// Multiplier for envelope times, higher notes get shorter envelopes.
+pub fn rate_scale(note: f32, amount: f32) -> f32 {
+    2.0_f32.powf(-amount * (note - RATE_SCALING_BASE_NOTE).max(0.0) / 24.0)
+}
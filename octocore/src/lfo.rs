@@ -0,0 +1,418 @@
+use crate::param::{Curve, Param, Unit};
+use fundsp::math::rnd1;
+use fundsp::prelude::{An, AudioNode, Frame, Shared, U0, U1};
+use std::f32::consts::{PI, TAU};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+/// Number of LFOs available as modulation sources.
+pub const LFOS: usize = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    SampleHold,
+    SmoothRandom,
+}
+
+impl LfoShape {
+    pub fn from_value(value: f32) -> Self {
+        LfoShape::iter()
+            .nth(value.round().max(0.0) as usize)
+            .unwrap_or(LfoShape::SmoothRandom)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LfoShape::Sine => "Sine",
+            LfoShape::Triangle => "Triangle",
+            LfoShape::Saw => "Saw",
+            LfoShape::Square => "Square",
+            LfoShape::SampleHold => "S&H",
+            LfoShape::SmoothRandom => "Smooth",
+        }
+    }
+
+    /// Bipolar value at `phase` in 0..1. Random shapes hold or glide from `from` to `to`
+    /// over one cycle.
+    pub fn value(&self, phase: f32, from: f32, to: f32) -> f32 {
+        match self {
+            LfoShape::Sine => (TAU * phase).sin(),
+            LfoShape::Triangle => {
+                if phase < 0.25 {
+                    4.0 * phase
+                } else if phase < 0.75 {
+                    2.0 - 4.0 * phase
+                } else {
+                    4.0 * phase - 4.0
+                }
+            }
+            LfoShape::Saw => 2.0 * (phase + 0.5).fract() - 1.0,
+            LfoShape::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            LfoShape::SampleHold => to,
+            LfoShape::SmoothRandom => from + (to - from) * (1.0 - (PI * phase).cos()) / 2.0,
+        }
+    }
+}
+
+/// Tempo synced rates as (name, beats per cycle), the first entry runs free at the rate in Hz.
+pub const SYNC_DIVISIONS: [(&str, f32); 11] = [
+    ("Off", 0.0),
+    ("4 Bars", 16.0),
+    ("2 Bars", 8.0),
+    ("1 Bar", 4.0),
+    ("1/2", 2.0),
+    ("1/4", 1.0),
+    ("1/8", 0.5),
+    ("1/8T", 1.0 / 3.0),
+    ("1/16", 0.25),
+    ("1/16T", 1.0 / 6.0),
+    ("1/32", 0.125),
+];
+
+fn sync_name(value: f32) -> &'static str {
+    SYNC_DIVISIONS[(value.round().max(0.0) as usize).min(SYNC_DIVISIONS.len() - 1)].0
+}
+
+#[derive(Clone)]
+pub struct LfoParams {
+    /// Index into [`LfoShape`]
+    pub shape: Param,
+    /// Rate in Hz while not synced
+    pub rate: Param,
+    /// Index into [`SYNC_DIVISIONS`]
+    pub sync: Param,
+    pub depth: Param,
+    /// Phase offset in 0..1, a retriggered cycle starts here
+    pub phase: Param,
+    /// Seconds to fade in after a note on
+    pub fade: Param,
+    /// Restart the cycle on every note on
+    pub retrigger: Param,
+    /// Run one LFO per voice instead of a single one for the whole synth
+    pub per_voice: Param,
+}
+
+impl Default for LfoParams {
+    fn default() -> Self {
+        Self {
+            shape: Param::new(0.0, (0.0, 5.0), None)
+                .with_name("Shape")
+                .with_step(1.0)
+                .with_formatter(|value| LfoShape::from_value(value).name().to_string()),
+            rate: Param::new(1.0, (0.01, 40.0), None)
+                .with_name("Rate")
+                .with_unit(Unit::Hz)
+                .with_curve(Curve::Log),
+            sync: Param::new(0.0, (0.0, (SYNC_DIVISIONS.len() - 1) as f32), None)
+                .with_name("Sync")
+                .with_step(1.0)
+                .with_formatter(|value| sync_name(value).to_string()),
+            depth: Param::new(1.0, (0.0, 1.0), None)
+                .with_name("Depth")
+                .with_unit(Unit::Percent),
+            phase: Param::new(0.0, (0.0, 1.0), None)
+                .with_name("Phase")
+                .with_unit(Unit::Percent),
+            fade: Param::new(0.0, (0.0, 10.0), None)
+                .with_name("Fade")
+                .with_unit(Unit::Seconds)
+                .with_curve(Curve::Skew(3.0)),
+            retrigger: Param::new(0.0, (0.0, 1.0), None)
+                .with_name("Retrig")
+                .with_step(1.0)
+                .with_formatter(|value| if value >= 0.5 { "On" } else { "Off" }.to_string()),
+            per_voice: Param::new(0.0, (0.0, 1.0), None)
+                .with_name("Mode")
+                .with_step(1.0)
+                .with_formatter(|value| if value >= 0.5 { "Voice" } else { "Global" }.to_string()),
+        }
+    }
+}
+
+impl LfoParams {
+    /// Parameters of the LFO page in encoder order.
+    pub fn page_params(&self) -> [&Param; 8] {
+        [
+            &self.shape,
+            &self.rate,
+            &self.sync,
+            &self.depth,
+            &self.phase,
+            &self.fade,
+            &self.retrigger,
+            &self.per_voice,
+        ]
+    }
+
+    /// Shape as seen by `voice`, or outside of the voices for `None`.
+    pub fn shape(&self, voice: Option<usize>) -> LfoShape {
+        LfoShape::from_value(self.shape.value_for(voice))
+    }
+
+    pub fn sync_division(&self, voice: Option<usize>) -> (&'static str, f32) {
+        SYNC_DIVISIONS[self.sync.value_for(voice).round() as usize]
+    }
+
+    pub fn is_per_voice(&self) -> bool {
+        self.per_voice.value() >= 0.5
+    }
+
+    /// Cycles per second, synced divisions follow `tempo` in BPM.
+    pub fn frequency(&self, tempo: f32, voice: Option<usize>) -> f32 {
+        match self.sync_division(voice) {
+            (_, beats) if beats > 0.0 => tempo / 60.0 / beats,
+            _ => self.rate.value_for(voice),
+        }
+    }
+}
+
+pub fn lfo(
+    params: &LfoParams,
+    tempo: &Param,
+    triggers: Vec<Shared>,
+    seed: u64,
+    voice: Option<usize>,
+) -> An<Lfo> {
+    An(Lfo::new(params, tempo, triggers, seed, voice))
+}
+
+/// Low frequency oscillator, bipolar and scaled by the depth.
+/// A change of the summed `triggers` counts as a note on, so one LFO can follow
+/// a single voice or the whole synth. The LFO of a voice follows the modulation of `voice`.
+/// - Output 0: LFO value.
+#[derive(Clone)]
+pub struct Lfo {
+    params: LfoParams,
+    tempo: Param,
+    triggers: Vec<Shared>,
+    seed: u64,
+    voice: Option<usize>,
+    sample_duration: f32,
+    last_trigger: f32,
+    phase: f32,
+    /// Seconds since the last note on
+    time: f32,
+    cycles: u64,
+    from: f32,
+    to: f32,
+}
+
+impl Lfo {
+    pub fn new(
+        params: &LfoParams,
+        tempo: &Param,
+        triggers: Vec<Shared>,
+        seed: u64,
+        voice: Option<usize>,
+    ) -> Self {
+        let mut lfo = Self {
+            params: params.clone(),
+            tempo: tempo.clone(),
+            triggers,
+            seed,
+            voice,
+            sample_duration: 0.0,
+            last_trigger: 0.0,
+            phase: 0.0,
+            time: 0.0,
+            cycles: 0,
+            from: 0.0,
+            to: 0.0,
+        };
+        lfo.reset();
+        lfo.set_sample_rate(fundsp::DEFAULT_SR);
+        lfo
+    }
+
+    fn value(&self, param: &Param) -> f32 {
+        param.value_for(self.voice)
+    }
+
+    fn trigger(&self) -> f32 {
+        self.triggers.iter().map(|trigger| trigger.value()).sum()
+    }
+
+    fn next_random(&mut self) {
+        self.cycles += 1;
+        self.from = self.to;
+        self.to = rnd1(self.seed.wrapping_add(self.cycles)) as f32 * 2.0 - 1.0;
+    }
+}
+
+impl AudioNode for Lfo {
+    const ID: u64 = 1343;
+    type Inputs = U0;
+    type Outputs = U1;
+
+    fn reset(&mut self) {
+        self.last_trigger = self.trigger();
+        self.phase = 0.0;
+        self.time = 0.0;
+        self.cycles = 0;
+        self.to = 0.0;
+        self.next_random();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_duration = (1.0 / sample_rate) as f32;
+    }
+
+    #[inline]
+    fn tick(&mut self, _input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let trigger = self.trigger();
+        if trigger != self.last_trigger {
+            self.last_trigger = trigger;
+            self.time = 0.0;
+            if self.value(&self.params.retrigger) >= 0.5 {
+                self.phase = 0.0;
+                self.next_random();
+            }
+        }
+        let phase = (self.phase + self.value(&self.params.phase)).fract();
+        let fade = self.value(&self.params.fade);
+        let fade_gain = if fade > 0.0 {
+            (self.time / fade).min(1.0)
+        } else {
+            1.0
+        };
+        let value = self
+            .params
+            .shape(self.voice)
+            .value(phase, self.from, self.to)
+            * self.value(&self.params.depth)
+            * fade_gain;
+
+        self.time += self.sample_duration;
+        self.phase += self.params.frequency(self.tempo.value(), self.voice) * self.sample_duration;
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            self.next_random();
+        }
+        [value].into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::param::{param_sink, Contributions, ModTarget};
+    use fundsp::prelude::{constant, shared};
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!(
+            (value - expected).abs() < 1e-3,
+            "{value} is not close to {expected}"
+        );
+    }
+
+    fn test_lfo(params: &LfoParams, trigger: &Shared) -> Lfo {
+        let tempo = Param::new(120.0, (20.0, 300.0), None);
+        let mut lfo = Lfo::new(params, &tempo, vec![trigger.clone()], 0, None);
+        lfo.set_sample_rate(1000.0);
+        lfo
+    }
+
+    fn run(lfo: &mut Lfo, ticks: usize) -> f32 {
+        let mut value = 0.0;
+        for _ in 0..ticks {
+            value = lfo.tick(&Frame::default())[0];
+        }
+        value
+    }
+
+    #[test]
+    fn periodic_shapes() {
+        let shapes = [
+            (LfoShape::Sine, [0.0, 1.0, 0.0, -1.0]),
+            (LfoShape::Triangle, [0.0, 1.0, 0.0, -1.0]),
+            (LfoShape::Saw, [0.0, 0.5, -1.0, -0.5]),
+            (LfoShape::Square, [1.0, 1.0, -1.0, -1.0]),
+        ];
+        for (shape, expected) in shapes {
+            for (quarter, value) in expected.iter().enumerate() {
+                assert_close(shape.value(quarter as f32 / 4.0, 0.0, 0.0), *value);
+            }
+        }
+        assert_close(LfoShape::SmoothRandom.value(0.5, -1.0, 0.5), -0.25);
+        assert_close(LfoShape::SampleHold.value(0.5, -1.0, 0.5), 0.5);
+    }
+
+    #[test]
+    fn synced_rate_follows_tempo() {
+        let params = LfoParams::default();
+        params.rate.set_value(3.0);
+        assert_eq!(params.frequency(120.0, None), 3.0);
+        params.sync.set_value(5.0);
+        assert_eq!(params.sync_division(None).0, "1/4");
+        assert_eq!(params.frequency(120.0, None), 2.0);
+    }
+
+    #[test]
+    fn retrigger_restarts_at_the_start_phase() {
+        let params = LfoParams::default();
+        params.shape.set_value(1.0);
+        params.rate.set_value(1.0);
+        params.phase.set_value(0.25);
+        let trigger = shared(0.0);
+        let mut lfo = test_lfo(&params, &trigger);
+        assert_close(run(&mut lfo, 1), 1.0);
+        assert_close(run(&mut lfo, 250), 0.0);
+
+        trigger.set_value(1.0);
+        assert!(run(&mut lfo, 1) < 0.0);
+        params.retrigger.set_value(1.0);
+        trigger.set_value(2.0);
+        assert_close(run(&mut lfo, 1), 1.0);
+    }
+
+    #[test]
+    fn fades_in_after_note_on() {
+        let params = LfoParams::default();
+        params.shape.set_value(3.0);
+        params.rate.set_value(0.01);
+        params.fade.set_value(0.1);
+        let trigger = shared(0.0);
+        let mut lfo = test_lfo(&params, &trigger);
+        run(&mut lfo, 200);
+
+        trigger.set_value(1.0);
+        assert_close(run(&mut lfo, 1), 0.0);
+        assert_close(run(&mut lfo, 50), 0.5);
+        assert_close(run(&mut lfo, 100), 1.0);
+    }
+
+    #[test]
+    fn voice_lfo_follows_per_voice_modulation() {
+        let params = LfoParams::default();
+        params.shape.set_value(3.0);
+        params.rate.set_value(0.01);
+        params.depth.set_value(0.0);
+        let tempo = Param::new(120.0, (20.0, 300.0), None);
+        let trigger = shared(0.0);
+        let voice_lfo = |voice| Lfo::new(&params, &tempo, vec![trigger.clone()], 0, voice);
+        let (mut voice_0, mut voice_1, mut global) =
+            (voice_lfo(Some(0)), voice_lfo(Some(1)), voice_lfo(None));
+
+        // e.g. velocity of voice 1 routed to the depth
+        let mut depth = constant(0.5)
+            >> param_sink(
+                &params.depth,
+                ModTarget::Voice(1),
+                &Contributions::default(),
+            );
+        depth.tick(&Frame::default());
+        assert_close(run(&mut voice_1, 1), 0.5);
+        assert_close(run(&mut voice_0, 1), 0.0);
+        assert_close(run(&mut global, 1), 0.0);
+    }
+}
@@ -1,12 +1,18 @@
 // Octocore synthesizer library
 pub mod adsr;
+pub mod algorithm;
 pub mod display;
+pub mod glide;
+pub mod key_scaling;
+pub mod lfo;
 pub mod midi;
 pub mod midi_input;
 pub mod midi_output;
 pub mod modulation;
-pub mod p_sine;
+pub mod mpe;
+pub mod p_wave;
 pub mod param;
+pub mod patch;
 pub mod poly;
 pub mod push;
 pub mod synth;
@@ -1,11 +1,17 @@
 mod adsr;
+mod algorithm;
 mod display;
+mod glide;
+mod key_scaling;
+mod lfo;
 mod midi;
 mod midi_input;
 mod midi_output;
 mod modulation;
-mod p_sine;
+mod mpe;
+mod p_wave;
 mod param;
+mod patch;
 mod poly;
 mod push;
 mod synth;
@@ -13,27 +19,32 @@ mod synth_params;
 mod ui;
 
 use crate::display::render_image;
+use crate::lfo::LFOS;
 use crate::midi::io::{get_midi_out_connection, get_midi_out_device};
 use crate::midi_input::{get_midi_device, run_input};
 use crate::midi_output::{init_midi_ui, send_ui_midi};
-use crate::modulation::create_modulation_list;
+use crate::modulation::{create_modulation_list, ModDestinations, ModRoutes, MOD_SLOTS};
 use crate::poly::MonoPoly;
 use crate::push::Push2;
-use crate::synth::{create_sound, run_output, sine_lfo};
+use crate::synth::{create_sound, global_lfo, mod_slot, run_output};
 use crate::synth_params::SynthParams;
-use crate::ui::ui_state::{InputEvent, OpPage, Page, UIState};
-use fundsp::prelude::{constant, pass, sumf, Net, NodeId, U128};
+use crate::ui::ui_state::{InputEvent, ModPage, OpPage, Page, UIState};
+use fundsp::prelude::{pass, sumf, Net, NodeId, U128};
 use midir::{MidiInput, MidiOutput};
+use std::path::Path;
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 
-fn render_loop(synth_params: SynthParams, uistate: UIState) {
+/// Patch loaded on start and saved on exit when no path is given.
+const DEFAULT_PATCH: &str = "patch.toml";
+
+fn render_loop(synth_params: SynthParams, uistate: UIState, dests: ModDestinations) {
     std::thread::spawn(move || {
         let mut push = Push2::new();
         let mut pixels: [u8; 2048 * 160] = [0; 2048 * 160];
         push.connect();
         loop {
-            let mut pixels = render_image(&synth_params, uistate.clone(), &mut pixels);
+            let mut pixels = render_image(&synth_params, uistate.clone(), &dests, &mut pixels);
             push.draw_image(&mut pixels).unwrap();
         }
     });
@@ -46,36 +57,55 @@ fn main() -> anyhow::Result<()> {
     let out_port = get_midi_out_device(&mut midi_out)?;
     let mut mono_poly = MonoPoly::new(8);
     let synth_params = SynthParams::new(mono_poly.voice_size);
+    let patch_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_PATCH.to_string());
+    if Path::new(&patch_path).exists() {
+        synth_params.load(&patch_path)?;
+        mono_poly.apply_settings(&synth_params.voice_settings, &synth_params.voice_params);
+    }
     let dests = create_modulation_list(&synth_params);
 
     let ui_state = UIState {
         page: Arc::new(Mutex::new(Page::Op(0))),
         op_subpage: Arc::new(Mutex::new(OpPage::Tone)),
-        lfo_dest: Arc::new(Mutex::new(dests[0].clone())),
+        mod_subpage: Arc::new(Mutex::new(ModPage::Matrix)),
+        mod_slot: Arc::new(Mutex::new(0)),
     };
 
-    render_loop(synth_params.clone(), ui_state.clone());
+    render_loop(synth_params.clone(), ui_state.clone(), dests.clone());
 
     let (ui_tx, ui_rx) = channel::<InputEvent>();
 
-    let mut net = Net::new(0, 1);
-    let voice_mixer_id = net.push(Box::new(sumf::<U128, _, _, f32>(|_| pass())));
+    let mut net = Net::new(0, 2);
+    let voice_mixer_id = net.push(Box::new(sumf::<U128, _, _, f32>(|_| pass() | pass())));
     net.connect_output(voice_mixer_id, 0, 0);
+    net.connect_output(voice_mixer_id, 1, 1);
 
     let voice_ids: Vec<(usize, NodeId)> = (0..mono_poly.voice_size)
         .map(|i| net.push(create_sound(&synth_params, i)))
         .enumerate()
         .collect();
-    for (i, id) in voice_ids {
-        net.connect(id, 0, voice_mixer_id, i)
+    for (i, id) in voice_ids.iter() {
+        net.connect(*id, 0, voice_mixer_id, *i * 2);
+        net.connect(*id, 1, voice_mixer_id, *i * 2 + 1);
     }
 
-    let dummy_dest = net.push(Box::new(constant(0.5))); //sine_lfo(&synth_params.ops[1].volume));
+    for index in 0..LFOS {
+        net.push(global_lfo(&synth_params, index));
+    }
+
+    let mut routes = ModRoutes::default();
+    // after the voices and LFOs it reads from, voices pick the offsets up in the next block
+    let slot_ids: Vec<NodeId> = (0..MOD_SLOTS)
+        .map(|slot| net.push(mod_slot(slot, &synth_params, &dests, &mut routes)))
+        .collect();
 
     let mut connection = get_midi_out_connection(midi_out, &out_port);
     run_output(net.backend());
     {
         let synth_params = synth_params.clone();
+        let dests = dests.clone();
         std::thread::spawn(move || {
             init_midi_ui(&mut connection);
             for event in ui_rx {
@@ -83,18 +113,39 @@ fn main() -> anyhow::Result<()> {
                 match event {
                     InputEvent::PageChange(_) => {}
                     InputEvent::OpSubpageChange(_) => {}
-                    InputEvent::LFO(dest) => {
-                        println!("{}", dest.name);
-                        dest.dest.set_modulation(0.0);
-                        net.replace(dummy_dest, sine_lfo(&dest.dest));
+                    InputEvent::ModSubpageChange(_) => {}
+                    InputEvent::ModSlotChange(slot) => {
+                        // the new node takes back what the old one wrote, once it runs
+                        net.replace(
+                            slot_ids[slot],
+                            mod_slot(slot, &synth_params, &dests, &mut routes),
+                        );
                         net.commit();
                     }
+                    InputEvent::AlgorithmChange(_) => {
+                        for (i, id) in voice_ids.iter() {
+                            net.replace(*id, create_sound(&synth_params, *i as u8));
+                        }
+                        net.commit();
+                    }
+                    InputEvent::VoiceSettingsChange => mono_poly
+                        .apply_settings(&synth_params.voice_settings, &synth_params.voice_params),
                     InputEvent::NoteOn { note, velocity } => {
                         mono_poly.on_voice_on(note, velocity, &synth_params.voice_params)
                     }
                     InputEvent::NoteOff { note } => {
                         mono_poly.on_voice_off(note, &synth_params.voice_params)
                     }
+                    InputEvent::PolyPressure { note, pressure } => {
+                        mono_poly.on_poly_pressure(note, pressure, &synth_params.voice_params)
+                    }
+                    InputEvent::Mpe(event) => mono_poly.on_mpe(event, &synth_params.voice_params),
+                    InputEvent::Sustain(down) => {
+                        mono_poly.on_sustain(down, &synth_params.voice_params)
+                    }
+                    InputEvent::Sostenuto(down) => {
+                        mono_poly.on_sostenuto(down, &synth_params.voice_params)
+                    }
                 }
             }
         });
@@ -103,9 +154,13 @@ fn main() -> anyhow::Result<()> {
     run_input(
         midi_in,
         in_port,
-        synth_params,
+        synth_params.clone(),
         ui_state,
         ui_tx.clone(),
         dests.to_vec(),
-    )
+    )?;
+    // the sound is picked up again on the next start
+    synth_params.save(&patch_path)?;
+    println!("Patch saved to '{patch_path}'");
+    Ok(())
 }
@@ -367,9 +367,51 @@ impl EncoderTurnMessage {
     }
 }
 
+/// Polyphonic aftertouch of a single pad.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PadPressureMessage {
+    pub pad: PushPad,
+    pub pressure: u8,
+}
+
+impl PadPressureMessage {
+    pub fn from_midi(message: &[u8]) -> Option<Self> {
+        if let [control_kind, control_number, pressure] = message {
+            match control_kind {
+                (0xA0..=0xAF) => PushPad::from_midi(*control_number).map(|pad| Self {
+                    pad,
+                    pressure: *pressure,
+                }),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// Pressure of the hardest pressed pad, sent when the Push is in channel pressure mode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChannelPressureMessage {
+    pub pressure: u8,
+}
+
+impl ChannelPressureMessage {
+    pub fn from_midi(message: &[u8]) -> Option<Self> {
+        match message {
+            [0xD0..=0xDF, pressure] => Some(Self {
+                pressure: *pressure,
+            }),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PushMessage {
     PadPress(PadMessage),
+    PadPressure(PadPressureMessage),
+    ChannelPressure(ChannelPressureMessage),
     ButtonPress(ButtonMessage),
     EncoderTouch(EncoderTouchMessage),
     EncoderTurn(EncoderTurnMessage),
@@ -379,6 +421,12 @@ impl PushMessage {
     pub fn from_midi(message: &[u8]) -> Option<Self> {
         match message {
             _ if let Some(msg) = PadMessage::from_midi(message) => Some(Self::PadPress(msg)),
+            _ if let Some(msg) = PadPressureMessage::from_midi(message) => {
+                Some(Self::PadPressure(msg))
+            }
+            _ if let Some(msg) = ChannelPressureMessage::from_midi(message) => {
+                Some(Self::ChannelPressure(msg))
+            }
             _ if let Some(msg) = ButtonMessage::from_midi(message) => Some(Self::ButtonPress(msg)),
             _ if let Some(msg) = EncoderTouchMessage::from_midi(message) => {
                 Some(Self::EncoderTouch(msg))
@@ -424,4 +472,21 @@ mod tests {
             Some(PushButton::LowerRow(TrackIndex::T8)),
         );
     }
+    #[test]
+    fn pressure_from_midi() {
+        assert_eq!(
+            PushMessage::from_midi(&[0xA0, 36, 90]),
+            Some(PushMessage::PadPressure(PadPressureMessage {
+                pad: PushPad::new(0),
+                pressure: 90,
+            })),
+        );
+        assert_eq!(
+            PushMessage::from_midi(&[0xD0, 64]),
+            Some(PushMessage::ChannelPressure(ChannelPressureMessage {
+                pressure: 64
+            })),
+        );
+        assert_eq!(PushMessage::from_midi(&[0xA0, 10, 90]), None);
+    }
 }
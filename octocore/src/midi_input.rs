@@ -1,15 +1,19 @@
-use crate::modulation::{ModDestination, ModDestinations};
+use crate::algorithm::Algorithm;
+use crate::lfo::LFOS;
+use crate::midi::controls::PushMessage;
+use crate::modulation::{ModDestinations, MOD_SLOTS};
+use crate::mpe::MpeParser;
 use crate::param::Param;
+use crate::synth::pitch_bend_factor;
 use crate::synth_params::{OpParams, SynthParams};
-use crate::ui::ui_state::{InputEvent, OpPage, Page, UIState};
+use crate::ui::ui_state::{InputEvent, ModPage, OpPage, Page, UIState};
 use anyhow::bail;
-use fundsp::shared::Shared;
-use fundsp::Float;
 use midi_msg::{ChannelVoiceMsg, ControlChange, MidiMsg};
-use midir::{Ignore, MidiInput, MidiInputPort};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort};
 use read_input::prelude::input;
 use read_input::prelude::*;
 use std::sync::mpsc::Sender;
+use strum::IntoEnumIterator;
 
 pub fn encoder_to_value(input: u8, value: f32, intensity: f32) -> f32 {
     if input > 32 {
@@ -19,16 +23,41 @@ pub fn encoder_to_value(input: u8, value: f32, intensity: f32) -> f32 {
     }
 }
 
-pub fn encoder_to_shared(input: u8, value: &Shared, intensity: f32) {
-    value.set_value(encoder_to_value(input, value.value().to_f32(), intensity))
+/// Encoder ticks per step of a stepped parameter.
+const STEP_TICKS: f32 = 4.0;
+/// Encoder ticks to sweep the whole range of a continuous parameter.
+const RANGE_TICKS: f32 = 256.0;
+/// Stepped parameters with more steps than this are swept like continuous ones.
+const MAX_STEPS: f32 = 32.0;
+
+/// Moves a parameter along its curve, stepped parameters move one step per few ticks.
+pub fn encoder_to_param(input: u8, param: &Param) {
+    let ticks = encoder_to_value(input, 0.0, 1.0);
+    match param.step() {
+        Some(step) if param.range() / step <= MAX_STEPS => {
+            param.set_value(param.unmodulated_value() + ticks * step / STEP_TICKS)
+        }
+        _ => param.set_normalized(param.normalized() + ticks / RANGE_TICKS),
+    }
+}
+
+/// Maps the main pots to `params` in order.
+fn pots_to_params(pot: &Pot, params: &[&Param]) {
+    let Pot::MainPot(id, value) = pot;
+    if let Some(param) = (*id as usize).checked_sub(1).and_then(|i| params.get(i)) {
+        encoder_to_param(*value, param)
+    }
 }
 
-pub fn encoder_to_param(input: u8, value: &Param, intensity: f32) {
-    value.set_value(encoder_to_value(
-        input,
-        value.unmodulated_value(),
-        intensity,
-    ))
+/// Steps through the variants of an enum, wrapping around at both ends.
+pub fn encoder_to_choice<T: IntoEnumIterator + PartialEq + Copy>(input: u8, current: T) -> T {
+    let choices: Vec<T> = T::iter().collect();
+    let index = choices
+        .iter()
+        .position(|choice| *choice == current)
+        .unwrap_or(0);
+    let next = encoder_to_value(input, index as f32, 1.).floor();
+    choices[next.rem_euclid(choices.len() as f32) as usize]
 }
 
 pub fn control_to_pages(control: ControlChange, ui: &UIState, in_tx: &Sender<InputEvent>) {
@@ -60,11 +89,34 @@ pub fn control_to_pages(control: ControlChange, ui: &UIState, in_tx: &Sender<Inp
                             .send(InputEvent::PageChange(Page::Modulation))
                             .unwrap();
                     }
+                    107 => {
+                        *page = Page::Algorithm;
+                        in_tx
+                            .send(InputEvent::PageChange(Page::Algorithm))
+                            .unwrap();
+                    }
+                    108 => {
+                        *page = Page::Voice;
+                        in_tx.send(InputEvent::PageChange(Page::Voice)).unwrap();
+                    }
                     //105 => { *page = Page::Op4; ui_tx.send(InputEvent::PageChange(Page::Op4)).unwrap(); }
                     _ => {}
                 }
             }
         }
+        ControlChange::CCHighRes {
+            control1,
+            control2: _,
+            value,
+        } if value > 0 && matches!(*page, Page::Modulation) => {
+            let mod_page = match control1 {
+                20 => ModPage::Matrix,
+                lfo if (21..21 + LFOS as u8).contains(&lfo) => ModPage::Lfo((lfo - 21) as usize),
+                _ => return,
+            };
+            *ui.mod_subpage.lock().unwrap() = mod_page;
+            in_tx.send(InputEvent::ModSubpageChange(mod_page)).unwrap();
+        }
         ControlChange::CCHighRes {
             control1,
             control2: _,
@@ -84,6 +136,24 @@ pub fn control_to_pages(control: ControlChange, ui: &UIState, in_tx: &Sender<Inp
                             .send(InputEvent::OpSubpageChange(OpPage::Amp))
                             .unwrap();
                     }
+                    22 => {
+                        *op_subpage = OpPage::Scaling;
+                        in_tx
+                            .send(InputEvent::OpSubpageChange(OpPage::Scaling))
+                            .unwrap();
+                    }
+                    23 => {
+                        *op_subpage = OpPage::Env;
+                        in_tx
+                            .send(InputEvent::OpSubpageChange(OpPage::Env))
+                            .unwrap();
+                    }
+                    24 => {
+                        *op_subpage = OpPage::Dx;
+                        in_tx
+                            .send(InputEvent::OpSubpageChange(OpPage::Dx))
+                            .unwrap();
+                    }
                     _ => {}
                 }
             }
@@ -98,25 +168,47 @@ pub enum Pot {
 
 pub fn pots_to_sub_page(pot: &Pot, op_subpage: OpPage, op_params: &OpParams) {
     match op_subpage {
-        OpPage::Tone => {
-            if let Pot::MainPot(id, value) = pot {
-                match id {
-                    1 => encoder_to_param(*value, &op_params.volume, 512.),
-                    2 => encoder_to_param(*value, &op_params.ratio, 1.),
-                    _ => {}
+        OpPage::Tone => pots_to_params(pot, &op_params.tone_params()),
+        OpPage::Amp => pots_to_params(pot, &op_params.adsr_params.amp_params()),
+        OpPage::Scaling => pots_to_params(pot, &op_params.scaling_params()),
+        OpPage::Env => pots_to_params(pot, &op_params.env_params()),
+        OpPage::Dx => pots_to_params(pot, &op_params.dx_params.params()),
+    }
+}
+
+fn pots_to_matrix(
+    pot: &Pot,
+    synth_params: &SynthParams,
+    ui: &UIState,
+    mod_dests: &ModDestinations,
+    in_tx: &Sender<InputEvent>,
+) {
+    let mut slot = ui.mod_slot.lock().unwrap();
+    let mod_slot = &synth_params.mod_slots[*slot];
+    if let Pot::MainPot(id, x) = *pot {
+        match id {
+            1 => {
+                let next = encoder_to_value(x, *slot as f32, 1.).floor();
+                *slot = next.rem_euclid(MOD_SLOTS as f32) as usize;
+            }
+            2 => {
+                {
+                    let mut source = mod_slot.source.lock().unwrap();
+                    *source = encoder_to_choice(x, *source);
                 }
+                in_tx.send(InputEvent::ModSlotChange(*slot)).unwrap();
             }
-        }
-        OpPage::Amp => {
-            if let Pot::MainPot(id, value) = pot {
-                match id {
-                    1 => encoder_to_shared(*value, &op_params.adsr_params.a, 32.),
-                    2 => encoder_to_shared(*value, &op_params.adsr_params.d, 32.),
-                    3 => encoder_to_shared(*value, &op_params.adsr_params.s, 32.),
-                    4 => encoder_to_shared(*value, &op_params.adsr_params.r, 32.),
-                    _ => {}
+            3 => {
+                {
+                    let mut dest = mod_slot.destination.lock().unwrap();
+                    let next = encoder_to_value(x, *dest as f32, 1.).floor();
+                    *dest = next.rem_euclid(mod_dests.len() as f32) as usize;
                 }
+                in_tx.send(InputEvent::ModSlotChange(*slot)).unwrap();
             }
+            4 => encoder_to_param(x, &mod_slot.amount),
+            8 => encoder_to_param(x, &synth_params.tempo),
+            _ => {}
         }
     }
 }
@@ -130,7 +222,6 @@ pub fn pots_to_controls<'a>(
 ) {
     let page = ui.page.lock().unwrap();
     let op_subpage = ui.op_subpage.lock().unwrap();
-    let mut dest = ui.lfo_dest.lock().unwrap();
 
     let pot = match control.to_simple() {
         ControlChange::CC {
@@ -173,21 +264,101 @@ pub fn pots_to_controls<'a>(
             Page::Op(x) => {
                 pots_to_sub_page(&pot, op_subpage.to_owned(), &voice_params.ops[x as usize])
             }
-            Page::Modulation => {
+            Page::Modulation => match *ui.mod_subpage.lock().unwrap() {
+                ModPage::Matrix => pots_to_matrix(&pot, voice_params, ui, mod_dests, in_tx),
+                ModPage::Lfo(index) => {
+                    pots_to_params(&pot, &voice_params.lfos[index].page_params())
+                }
+            },
+            Page::Algorithm => {
                 if let Pot::MainPot(1, x) = pot {
-                    let index = encoder_to_value(x, dest.to_owned().0 as f32, 1.).floor() as usize
-                        % mod_dests.len();
-                    *dest = mod_dests[index].clone();
-                    in_tx
-                        .send(InputEvent::LFO(dest.to_owned().1.clone()))
-                        .unwrap();
+                    let mut algorithm = voice_params.algorithm.lock().unwrap();
+                    let count = Algorithm::iter().count() as f32;
+                    let index = encoder_to_value(x, algorithm.index() as f32, 1.).floor();
+                    let next = Algorithm::from_index(index.rem_euclid(count) as usize);
+                    if next != *algorithm {
+                        *algorithm = next;
+                        in_tx.send(InputEvent::AlgorithmChange(next)).unwrap();
+                    }
+                }
+            }
+            Page::Voice => {
+                let settings = &voice_params.voice_settings;
+                if let Pot::MainPot(id, x) = pot {
+                    match id {
+                        1 => {
+                            let mut mode = settings.mode.lock().unwrap();
+                            *mode = encoder_to_choice(x, *mode);
+                        }
+                        2 => {
+                            let mut priority = settings.priority.lock().unwrap();
+                            *priority = encoder_to_choice(x, *priority);
+                        }
+                        3 => encoder_to_param(x, &settings.glide),
+                        4 => encoder_to_param(x, &settings.unison),
+                        5 => encoder_to_param(x, &settings.unison_detune),
+                        6 => encoder_to_param(x, &settings.unison_spread),
+                        7 => encoder_to_param(x, &settings.bend_up),
+                        8 => encoder_to_param(x, &settings.bend_down),
+                        _ => return,
+                    }
+                    in_tx.send(InputEvent::VoiceSettingsChange).unwrap();
                 }
             }
-            _ => {}
         }
     }
 }
 
+pub fn apply_channel_pressure(pressure: u8, synth_params: &SynthParams) {
+    for voice_params in synth_params.voice_params.iter() {
+        voice_params.pressure.set_value(pressure as f32 / 127.0);
+    }
+}
+
+pub fn apply_pitch_bend(bend: u16, synth_params: &SynthParams) {
+    let settings = &synth_params.voice_settings;
+    let factor = pitch_bend_factor(bend, settings.bend_up.value(), settings.bend_down.value());
+    for voice_params in synth_params.voice_params.iter() {
+        voice_params.pitch_bend.set_value(factor);
+    }
+}
+
+/// Handles what every keyboard sends: notes, pitch bend and pedals.
+pub fn play_to_params(
+    msg: ChannelVoiceMsg,
+    synth_params: &SynthParams,
+    in_tx: &Sender<InputEvent>,
+) {
+    match msg {
+        ChannelVoiceMsg::NoteOn { note, velocity } => {
+            in_tx.send(InputEvent::NoteOn { note, velocity }).unwrap()
+        }
+        ChannelVoiceMsg::NoteOff { note, velocity: _ } => {
+            in_tx.send(InputEvent::NoteOff { note }).unwrap()
+        }
+        ChannelVoiceMsg::PitchBend { bend } => apply_pitch_bend(bend, synth_params),
+        ChannelVoiceMsg::PolyPressure { note, pressure } => in_tx
+            .send(InputEvent::PolyPressure { note, pressure })
+            .unwrap(),
+        ChannelVoiceMsg::ChannelPressure { pressure } => {
+            apply_channel_pressure(pressure, synth_params)
+        }
+        ChannelVoiceMsg::ControlChange { control } => match control.to_simple() {
+            ControlChange::CC { control: 1, value } => {
+                synth_params.mod_wheel.set_value(value as f32 / 127.0)
+            }
+            ControlChange::CC { control: 64, value } => {
+                in_tx.send(InputEvent::Sustain(value >= 64)).unwrap()
+            }
+            ControlChange::CC { control: 66, value } => {
+                in_tx.send(InputEvent::Sostenuto(value >= 64)).unwrap()
+            }
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
 pub fn midi_to_params(
     midi_msg: MidiMsg,
     voice_params: &SynthParams,
@@ -199,19 +370,11 @@ pub fn midi_to_params(
         MidiMsg::ChannelVoice { channel, msg } => {
             println!("Received {channel} {msg:?}");
             match msg {
-                ChannelVoiceMsg::NoteOn { note, velocity } => {
-                    if note > 12 {
-                        // filter encoder touches on push 2
-                        in_tx.send(InputEvent::NoteOn { note, velocity }).unwrap()
-                    }
-                }
-                ChannelVoiceMsg::NoteOff { note, velocity: _ } => {
-                    in_tx.send(InputEvent::NoteOff { note }).unwrap()
+                ChannelVoiceMsg::NoteOn { note, .. } if note <= 12 => {
+                    // filter encoder touches on push 2
                 }
-                // ChannelVoiceMsg::PitchBend { bend } => {
-                // 	voice_params.pitch_bend.set_value(pitch_bend_factor(bend));
-                // }
                 ChannelVoiceMsg::ControlChange { control } => {
+                    play_to_params(msg, voice_params, in_tx);
                     control_to_pages(control, ui, &in_tx);
                     pots_to_controls(control, voice_params, ui, mod_destinations, in_tx);
                     if let ControlChange::CC {
@@ -226,13 +389,64 @@ pub fn midi_to_params(
                         // }
                     }
                 }
-                _ => {}
+                // the touch strip sends pitch bend
+                _ => play_to_params(msg, voice_params, in_tx),
             }
         }
         _ => {}
     }
 }
 
+/// Connects every MIDI input other than the Push 2, so notes, pitch bend and pedals
+/// can come from any keyboard. Controllers switch to MPE by sending their zone configuration.
+fn connect_external_inputs(
+    synth_params: &SynthParams,
+    in_tx: &Sender<InputEvent>,
+) -> Vec<MidiInputConnection<MpeParser>> {
+    let Ok(midi_in) = MidiInput::new("midir external input") else {
+        return vec![];
+    };
+    let ports: Vec<(MidiInputPort, String)> = midi_in
+        .ports()
+        .into_iter()
+        .filter_map(|port| midi_in.port_name(&port).ok().map(|name| (port, name)))
+        .filter(|(_, name)| !name.contains("Ableton Push 2"))
+        .collect();
+    ports
+        .into_iter()
+        .filter_map(|(port, name)| {
+            let mut midi_in = MidiInput::new("midir external input").ok()?;
+            midi_in.ignore(Ignore::None);
+            let synth_params = synth_params.clone();
+            let in_tx = in_tx.clone();
+            let connection = midi_in.connect(
+                &port,
+                "midir-read-external-input",
+                move |_stamp, message, mpe| {
+                    if let Some(event) = mpe.parse(message) {
+                        in_tx.send(InputEvent::Mpe(event)).unwrap()
+                    } else if let Ok((MidiMsg::ChannelVoice { msg, .. }, _len)) =
+                        MidiMsg::from_midi(message)
+                    {
+                        play_to_params(msg, &synth_params, &in_tx)
+                    }
+                },
+                MpeParser::default(),
+            );
+            match connection {
+                Ok(connection) => {
+                    println!("Reading input from '{name}'");
+                    Some(connection)
+                }
+                Err(e) => {
+                    println!("Cannot connect to '{name}': {e}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
 pub fn run_input(
     midi_in: MidiInput,
     in_port: MidiInputPort,
@@ -243,12 +457,24 @@ pub fn run_input(
 ) -> anyhow::Result<()> {
     println!("\nOpening connection");
     let in_port_name = midi_in.port_name(&in_port)?;
+    let _external_conns = connect_external_inputs(&voice_params, &in_tx);
     let _conn_in = midi_in.connect(
         &in_port,
         "midir-read-input",
-        move |_stamp, message, _| {
-            let (msg, _len) = MidiMsg::from_midi(message).unwrap();
-            midi_to_params(msg, &voice_params, &ui, &in_tx, &mod_destinations)
+        move |_stamp, message, _| match PushMessage::from_midi(message) {
+            Some(PushMessage::PadPressure(msg)) => in_tx
+                .send(InputEvent::PolyPressure {
+                    note: msg.pad.to_midi(),
+                    pressure: msg.pressure,
+                })
+                .unwrap(),
+            Some(PushMessage::ChannelPressure(msg)) => {
+                apply_channel_pressure(msg.pressure, &voice_params)
+            }
+            _ => {
+                let (msg, _len) = MidiMsg::from_midi(message).unwrap();
+                midi_to_params(msg, &voice_params, &ui, &in_tx, &mod_destinations)
+            }
         },
         (),
     );
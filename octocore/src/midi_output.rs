@@ -1,4 +1,4 @@
-use crate::ui::ui_state::{InputEvent, OpPage, Page};
+use crate::ui::ui_state::{InputEvent, ModPage, OpPage, Page};
 use anyhow::bail;
 use midi_msg::MidiMsg;
 use midi_msg::{Channel, ChannelVoiceMsg, ControlChange};
@@ -12,11 +12,11 @@ fn send_cc(conn: &mut MidiOutputConnection, control: ControlChange) {
     .expect(&format!("Cannot send {control:?}"));
 }
 
-const FIRST_LEDS_ROW: [u8; 5] = [
-    102, 103, 104, 105, 106, // , 107, 108, 109
+const FIRST_LEDS_ROW: [u8; 7] = [
+    102, 103, 104, 105, 106, 107, 108, // , 109
 ];
-const SECOND_LEDS_ROW: [u8; 2] = [
-    20, 21, // , 22, 23, 24, 25, 26, 27
+const SECOND_LEDS_ROW: [u8; 5] = [
+    20, 21, 22, 23, 24, // , 25, 26, 27
 ];
 
 struct Led {
@@ -77,7 +77,46 @@ pub fn send_ui_midi(event: &InputEvent, conn: &mut MidiOutputConnection) {
                 SECOND_LEDS_ROW,
                 conn,
             ),
+            OpPage::Scaling => send_switch(
+                Led {
+                    led_num: SECOND_LEDS_ROW[2],
+                    led_color: 122,
+                    neutral_color: 124,
+                },
+                SECOND_LEDS_ROW,
+                conn,
+            ),
+            OpPage::Env => send_switch(
+                Led {
+                    led_num: SECOND_LEDS_ROW[3],
+                    led_color: 122,
+                    neutral_color: 124,
+                },
+                SECOND_LEDS_ROW,
+                conn,
+            ),
+            OpPage::Dx => send_switch(
+                Led {
+                    led_num: SECOND_LEDS_ROW[4],
+                    led_color: 122,
+                    neutral_color: 124,
+                },
+                SECOND_LEDS_ROW,
+                conn,
+            ),
         },
+        InputEvent::ModSubpageChange(page) => send_switch(
+            Led {
+                led_num: match page {
+                    ModPage::Matrix => SECOND_LEDS_ROW[0],
+                    ModPage::Lfo(index) => SECOND_LEDS_ROW[1 + index],
+                },
+                led_color: 122,
+                neutral_color: 124,
+            },
+            SECOND_LEDS_ROW,
+            conn,
+        ),
         InputEvent::PageChange(page) => match page {
             Page::Op(0) => send_switch(
                 Led {
@@ -124,6 +163,24 @@ pub fn send_ui_midi(event: &InputEvent, conn: &mut MidiOutputConnection) {
                 FIRST_LEDS_ROW,
                 conn,
             ),
+            Page::Algorithm => send_switch(
+                Led {
+                    led_num: FIRST_LEDS_ROW[5],
+                    led_color: 122,
+                    neutral_color: 124,
+                },
+                FIRST_LEDS_ROW,
+                conn,
+            ),
+            Page::Voice => send_switch(
+                Led {
+                    led_num: FIRST_LEDS_ROW[6],
+                    led_color: 122,
+                    neutral_color: 124,
+                },
+                FIRST_LEDS_ROW,
+                conn,
+            ),
             _ => {}
         },
         _ => {}
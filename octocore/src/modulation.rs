@@ -1,30 +1,259 @@
-use crate::param::Param;
+use crate::param::{Contributions, Param, Unit};
+use crate::patch::{adsr_fields, dx_fields, lfo_fields, op_fields, voice_fields};
 use crate::synth_params::SynthParams;
+use std::sync::{Arc, Mutex};
+use strum_macros::EnumIter;
+
+/// Number of modulation matrix slots, see [`crate::synth::mod_slot`].
+pub const MOD_SLOTS: usize = 8;
+
+/// What drives a modulation slot. Everything but the mod wheel modulates each voice on its own.
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
+pub enum ModSource {
+    Off,
+    Lfo1,
+    Lfo2,
+    /// Envelope level of an operator
+    Env1,
+    Env2,
+    Env3,
+    Env4,
+    Velocity,
+    /// Played note, 0..1 over the whole MIDI range
+    Key,
+    ModWheel,
+    /// Polyphonic aftertouch
+    Aftertouch,
+    /// Channel pressure, per note with MPE
+    Pressure,
+    /// MPE slide
+    Slide,
+}
+
+impl ModSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModSource::Off => "Off",
+            ModSource::Lfo1 => "LFO 1",
+            ModSource::Lfo2 => "LFO 2",
+            ModSource::Env1 => "Env 1",
+            ModSource::Env2 => "Env 2",
+            ModSource::Env3 => "Env 3",
+            ModSource::Env4 => "Env 4",
+            ModSource::Velocity => "Velocity",
+            ModSource::Key => "Key",
+            ModSource::ModWheel => "Mod Whl",
+            ModSource::Aftertouch => "Aftertch",
+            ModSource::Pressure => "Pressure",
+            ModSource::Slide => "Slide",
+        }
+    }
+}
+
+/// One row of the modulation matrix.
+#[derive(Clone)]
+pub struct ModSlot {
+    pub source: Arc<Mutex<ModSource>>,
+    /// Index into [`create_modulation_list`]
+    pub destination: Arc<Mutex<usize>>,
+    /// Bipolar, 1 sweeps the whole range of the destination
+    pub amount: Param,
+}
+
+impl Default for ModSlot {
+    fn default() -> Self {
+        Self {
+            source: Arc::new(Mutex::new(ModSource::Off)),
+            destination: Arc::new(Mutex::new(0)),
+            amount: Param::new(0.0, (-1.0, 1.0), None)
+                .with_name("Amount")
+                .with_unit(Unit::Percent),
+        }
+    }
+}
+
+impl ModSlot {
+    pub fn source(&self) -> ModSource {
+        *self.source.lock().unwrap()
+    }
+
+    pub fn destination(&self) -> usize {
+        *self.destination.lock().unwrap()
+    }
+}
 
 #[derive(Clone)]
 pub struct ModDestination {
+    /// Stable name stored in patches, built from the patch field names, e.g. `op1.adsr.attack`
+    pub key: String,
     pub name: String,
     pub dest: Param,
 }
 
 pub type ModDestinations = Vec<(usize, ModDestination)>;
 
+/// Every param stored in a patch, named after its page.
+/// Built once, the list only changes with the number of operators, LFOs and slots.
 pub fn create_modulation_list(synth_params: &SynthParams) -> ModDestinations {
-    let synth_params = synth_params.clone();
-    vec![
-        ModDestination {
-            name: String::from("Vol"),
-            dest: synth_params.ops[1].volume.clone(),
-        },
-        ModDestination {
-            name: String::from("Ratio"),
-            dest: synth_params.ops[1].ratio.clone(),
-        },
-        // ModDestination {name: String::from("Vol"), dest: synth_params.op1.volume},
-        // ModDestination {name: String::from("Ratio"), dest: synth_params.op1.ratio},
-    ]
-    .iter()
-    .enumerate()
-    .map(|(i, dest)| (i, dest.clone()))
-    .collect()
+    let mut dests = vec![];
+    let mut add = |key_prefix: &str, name_prefix: &str, fields: &[(&'static str, &Param)]| {
+        dests.extend(fields.iter().map(|(key, param)| ModDestination {
+            key: format!("{key_prefix}{key}"),
+            name: format!("{name_prefix}{}", param.name()),
+            dest: (*param).clone(),
+        }))
+    };
+    for (op, op_params) in synth_params.ops.iter().enumerate() {
+        let (key, name) = (format!("op{}.", op + 1), format!("Op{} ", op + 1));
+        add(&key, &name, &op_fields(op_params));
+        add(
+            &format!("{key}adsr."),
+            &name,
+            &adsr_fields(&op_params.adsr_params),
+        );
+        add(
+            &format!("{key}dx."),
+            &name,
+            &dx_fields(&op_params.dx_params),
+        );
+    }
+    for (lfo, lfo_params) in synth_params.lfos.iter().enumerate() {
+        add(
+            &format!("lfo{}.", lfo + 1),
+            &format!("LFO{} ", lfo + 1),
+            &lfo_fields(lfo_params),
+        );
+    }
+    add(
+        "voice.",
+        "Voice ",
+        &voice_fields(&synth_params.voice_settings),
+    );
+    add("", "", &[("tempo", &synth_params.tempo)]);
+    for (slot, mod_slot) in synth_params.mod_slots.iter().enumerate() {
+        add(
+            &format!("slot{}.", slot + 1),
+            &format!("Slot{} ", slot + 1),
+            &[("amount", &mod_slot.amount)],
+        );
+    }
+    dests.into_iter().enumerate().collect()
+}
+
+/// Where every slot writes, plus the routings it left whose offsets were not taken back yet.
+/// Offsets may only be withdrawn on the audio thread, once the slot node writing them is
+/// gone, see [`crate::param::ParamRelease`].
+#[derive(Clone)]
+pub struct ModRoutes {
+    current: Vec<Option<(Param, Contributions)>>,
+    retired: Vec<Vec<(Param, Contributions)>>,
+}
+
+impl Default for ModRoutes {
+    fn default() -> Self {
+        Self {
+            current: vec![None; MOD_SLOTS],
+            retired: vec![vec![]; MOD_SLOTS],
+        }
+    }
+}
+
+impl ModRoutes {
+    /// Routes `slot` to `dest`. Returns the contributions of the new route and every retired
+    /// routing of the slot still to withdraw, a committed net may never run before the next.
+    pub fn reroute(
+        &mut self,
+        slot: usize,
+        dest: &Param,
+    ) -> (Contributions, Vec<(Param, Contributions)>) {
+        let contributions = Contributions::default();
+        let retired = &mut self.retired[slot];
+        retired.retain(|(_, previous)| !previous.is_withdrawn());
+        if let Some(previous) = self.current[slot].replace((dest.clone(), contributions.clone())) {
+            retired.push(previous);
+        }
+        (contributions, retired.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lfo::LFOS;
+    use crate::param::{param_release, param_sink, ModTarget};
+    use fundsp::prelude::{constant, AudioNode, Frame};
+
+    #[test]
+    fn every_patch_param_is_a_destination() {
+        let synth_params = SynthParams::default();
+        let dests = create_modulation_list(&synth_params);
+        let op = &synth_params.ops[0];
+        let op_count = 4
+            * (op_fields(op).len()
+                + adsr_fields(&op.adsr_params).len()
+                + dx_fields(&op.dx_params).len());
+        let lfo_count = LFOS * lfo_fields(&synth_params.lfos[0]).len();
+        let voice_count = voice_fields(&synth_params.voice_settings).len();
+        assert_eq!(
+            dests.len(),
+            op_count + lfo_count + voice_count + 1 + MOD_SLOTS
+        );
+        assert_eq!(dests[0].1.name, "Op1 Ratio");
+        assert_eq!(dests[0].1.key, "op1.ratio");
+        assert_eq!(dests[op_fields(op).len()].1.key, "op1.adsr.attack");
+        assert_eq!(dests[op_count + 1].1.name, "LFO1 Rate");
+        assert_eq!(dests[op_count + 1].1.key, "lfo1.rate");
+        assert_eq!(dests[op_count + lfo_count].1.name, "Voice Glide");
+        assert_eq!(dests[op_count + lfo_count + voice_count].1.name, "Tempo");
+        assert_eq!(dests.last().unwrap().1.name, "Slot8 Amount");
+        assert_eq!(dests.last().unwrap().1.key, "slot8.amount");
+        assert!(dests.iter().enumerate().all(|(i, (index, _))| i == *index));
+
+        // destinations share the value of the param they stand for
+        dests[op_count + lfo_count].1.dest.set_value(1.0);
+        assert_eq!(synth_params.voice_settings.glide.unmodulated_value(), 1.0);
+    }
+
+    #[test]
+    fn slots_sum_into_the_destination() {
+        let param = Param::new(0.5, (0.0, 1.0), None);
+        let (first, second) = (Contributions::default(), Contributions::default());
+        let mut slots = (constant(0.25) >> param_sink(&param, ModTarget::Global, &first))
+            | (constant(-0.5) >> param_sink(&param, ModTarget::Global, &second));
+        slots.tick(&Frame::default());
+        assert_eq!(param.value(), 0.25);
+
+        let mut second_slot = constant(0.5) >> param_sink(&param, ModTarget::Global, &second);
+        second_slot.tick(&Frame::default());
+        assert_eq!(param.value(), 1.0);
+    }
+
+    #[test]
+    fn rerouting_takes_the_old_offsets_back() {
+        let (first, second) = (
+            Param::new(0.0, (0.0, 1.0), None),
+            Param::new(0.0, (0.0, 1.0), None),
+        );
+        let mut routes = ModRoutes::default();
+        let (contributions, retired) = routes.reroute(0, &first);
+        assert!(retired.is_empty());
+        let mut old_slot = constant(0.3) >> param_sink(&first, ModTarget::Global, &contributions);
+        old_slot.tick(&Frame::default());
+
+        // the net routed to `second` is replaced before it ever runs
+        routes.reroute(0, &second);
+        let (_, retired) = routes.reroute(0, &first);
+        assert_eq!(retired.len(), 2);
+        // the old slot keeps writing until the new net takes over
+        old_slot.tick(&Frame::default());
+        assert_eq!(first.value(), 0.3);
+
+        let mut release = param_release(retired);
+        release.tick(&Frame::default());
+        assert_eq!(first.value(), 0.0);
+        assert!(contributions.is_withdrawn());
+        // released routings are not handed on again
+        let (_, retired) = routes.reroute(0, &second);
+        assert_eq!(retired.len(), 1);
+    }
 }
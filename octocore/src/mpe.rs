@@ -0,0 +1,226 @@
+/// Pitch bend range of member channels until the controller sets its own.
+const DEFAULT_MEMBER_BEND_RANGE: f32 = 48.0;
+const LOWER_MANAGER: u8 = 0;
+const UPPER_MANAGER: u8 = 15;
+/// Registered parameter carrying the MPE configuration message.
+const RPN_MCM: (u8, u8) = (0, 6);
+const RPN_BEND_RANGE: (u8, u8) = (0, 0);
+const SLIDE_CC: u8 = 74;
+
+/// Per note expression of a member channel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MpeEvent {
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8 },
+    Bend { channel: u8, semitones: f32 },
+    Pressure { channel: u8, pressure: u8 },
+    Slide { channel: u8, slide: u8 },
+}
+
+/// Reads the MPE configuration and turns messages on member channels into [`MpeEvent`]s.
+/// Works on raw MIDI bytes so it does not depend on the MIDI backend.
+#[derive(Debug, Clone)]
+pub struct MpeParser {
+    /// Member channel count of the lower zone, managed on channel 1
+    pub lower_members: u8,
+    /// Member channel count of the upper zone, managed on channel 16
+    pub upper_members: u8,
+    pub member_bend_range: f32,
+    /// Selected registered parameter per channel, as (MSB, LSB)
+    rpn: [(u8, u8); 16],
+}
+
+impl Default for MpeParser {
+    fn default() -> Self {
+        Self {
+            lower_members: 0,
+            upper_members: 0,
+            member_bend_range: DEFAULT_MEMBER_BEND_RANGE,
+            rpn: [(127, 127); 16],
+        }
+    }
+}
+
+impl MpeParser {
+    pub fn is_member(&self, channel: u8) -> bool {
+        let lower = LOWER_MANAGER < channel && channel <= LOWER_MANAGER + self.lower_members;
+        let upper = UPPER_MANAGER - self.upper_members <= channel && channel < UPPER_MANAGER;
+        lower || upper
+    }
+
+    fn configure(&mut self, manager: u8, members: u8) {
+        let members = members.min(15);
+        // zones never share channels, the newest configuration wins
+        match manager {
+            LOWER_MANAGER => {
+                self.lower_members = members;
+                self.upper_members = self.upper_members.min(14 - members.min(14));
+            }
+            UPPER_MANAGER => {
+                self.upper_members = members;
+                self.lower_members = self.lower_members.min(14 - members.min(14));
+            }
+            _ => {}
+        }
+    }
+
+    fn data_entry(&mut self, channel: u8, value: u8) {
+        match self.rpn[channel as usize] {
+            RPN_MCM => self.configure(channel, value),
+            RPN_BEND_RANGE if self.is_member(channel) => self.member_bend_range = value as f32,
+            _ => {}
+        }
+    }
+
+    /// Returns an event for messages on member channels. Everything else, including
+    /// the manager channels, is left to the regular input handling.
+    pub fn parse(&mut self, message: &[u8]) -> Option<MpeEvent> {
+        let (status, channel) = (message.first()? & 0xF0, message.first()? & 0x0F);
+        if status == 0xF0 {
+            // system messages have no channel
+            return None;
+        }
+        if status == 0xB0 {
+            if let [_, control, value] = message {
+                match control {
+                    101 => self.rpn[channel as usize].0 = *value,
+                    100 => self.rpn[channel as usize].1 = *value,
+                    6 => self.data_entry(channel, *value),
+                    _ => {}
+                }
+            }
+        }
+        if !self.is_member(channel) {
+            return None;
+        }
+        match (status, message) {
+            (0x90, [_, note, 0]) | (0x80, [_, note, _]) => Some(MpeEvent::NoteOff {
+                channel,
+                note: *note,
+            }),
+            (0x90, [_, note, velocity]) => Some(MpeEvent::NoteOn {
+                channel,
+                note: *note,
+                velocity: *velocity,
+            }),
+            (0xE0, [_, lsb, msb]) => {
+                let bend = ((*msb as u16) << 7) | *lsb as u16;
+                let amount = (bend as f32 - 8192.0) / 8192.0;
+                Some(MpeEvent::Bend {
+                    channel,
+                    semitones: amount * self.member_bend_range,
+                })
+            }
+            (0xD0, [_, pressure]) => Some(MpeEvent::Pressure {
+                channel,
+                pressure: *pressure,
+            }),
+            (0xB0, [_, SLIDE_CC, slide]) => Some(MpeEvent::Slide {
+                channel,
+                slide: *slide,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configure(parser: &mut MpeParser, manager: u8, members: u8) {
+        let cc = 0xB0 | manager;
+        assert_eq!(parser.parse(&[cc, 101, 0]), None);
+        assert_eq!(parser.parse(&[cc, 100, 6]), None);
+        assert_eq!(parser.parse(&[cc, 6, members]), None);
+    }
+
+    #[test]
+    fn ignores_everything_without_a_zone() {
+        let mut parser = MpeParser::default();
+        assert_eq!(parser.parse(&[0x91, 60, 100]), None);
+        assert_eq!(parser.parse(&[0xE1, 0, 64]), None);
+    }
+
+    #[test]
+    fn configuration_message_sets_zones() {
+        let mut parser = MpeParser::default();
+        configure(&mut parser, 0, 7);
+        assert!(!parser.is_member(0));
+        assert!(parser.is_member(1));
+        assert!(parser.is_member(7));
+        assert!(!parser.is_member(8));
+
+        configure(&mut parser, 15, 10);
+        assert_eq!(parser.upper_members, 10);
+        assert_eq!(parser.lower_members, 4);
+        assert!(parser.is_member(5));
+        assert!(parser.is_member(14));
+        assert!(!parser.is_member(15));
+
+        configure(&mut parser, 0, 0);
+        assert!(!parser.is_member(1));
+    }
+
+    #[test]
+    fn member_channel_messages() {
+        let mut parser = MpeParser::default();
+        configure(&mut parser, 0, 15);
+        assert_eq!(
+            parser.parse(&[0x92, 60, 100]),
+            Some(MpeEvent::NoteOn {
+                channel: 2,
+                note: 60,
+                velocity: 100
+            })
+        );
+        assert_eq!(
+            parser.parse(&[0x92, 60, 0]),
+            Some(MpeEvent::NoteOff {
+                channel: 2,
+                note: 60
+            })
+        );
+        assert_eq!(
+            parser.parse(&[0xE3, 0, 96]),
+            Some(MpeEvent::Bend {
+                channel: 3,
+                semitones: 24.0
+            })
+        );
+        assert_eq!(
+            parser.parse(&[0xD4, 90]),
+            Some(MpeEvent::Pressure {
+                channel: 4,
+                pressure: 90
+            })
+        );
+        assert_eq!(
+            parser.parse(&[0xB5, 74, 30]),
+            Some(MpeEvent::Slide {
+                channel: 5,
+                slide: 30
+            })
+        );
+        // manager channel messages are not per note
+        assert_eq!(parser.parse(&[0xE0, 0, 96]), None);
+        assert_eq!(parser.parse(&[0x90, 60, 100]), None);
+    }
+
+    #[test]
+    fn member_bend_range_from_rpn() {
+        let mut parser = MpeParser::default();
+        configure(&mut parser, 0, 15);
+        parser.parse(&[0xB1, 101, 0]);
+        parser.parse(&[0xB1, 100, 0]);
+        parser.parse(&[0xB1, 6, 12]);
+        assert_eq!(parser.member_bend_range, 12.0);
+        assert_eq!(
+            parser.parse(&[0xE1, 0, 0]),
+            Some(MpeEvent::Bend {
+                channel: 1,
+                semitones: -12.0
+            })
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use fundsp::math::{rnd1, sin};
+use fundsp::prelude::{An, AudioNode, Frame, SignalFrame};
+use fundsp::signal::{Routing, Signal};
+use fundsp::{convert, Real};
+use once_cell::sync::OnceCell;
+use std::f32::consts::{PI, TAU};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+/// Operator waveforms, in the order they are selected from the encoder.
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
+pub enum Waveform {
+    Sine,
+    HalfSine,
+    AbsSine,
+    QuarterSine,
+    Square,
+    Saw,
+    Noise,
+}
+
+impl Waveform {
+    pub fn from_value(value: f32) -> Self {
+        Waveform::iter()
+            .nth(value.round().max(0.0) as usize)
+            .unwrap_or(Waveform::Noise)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Waveform::Sine => "Sine",
+            Waveform::HalfSine => "Half",
+            Waveform::AbsSine => "Abs",
+            Waveform::QuarterSine => "Quarter",
+            Waveform::Square => "Square",
+            Waveform::Saw => "Saw",
+            Waveform::Noise => "Noise",
+        }
+    }
+
+    /// Value at `phase` in 0..1. Band limited shapes pick their table by the fundamental
+    /// as a fraction of the sample rate, `noise` is returned as is for [`Waveform::Noise`].
+    pub fn value(&self, phase: f32, frequency_ratio: f32, noise: f32) -> f32 {
+        match self {
+            Waveform::Sine => sin(phase * TAU),
+            Waveform::HalfSine => sin(phase * TAU).max(0.0),
+            Waveform::AbsSine => sin(phase * TAU).abs(),
+            Waveform::QuarterSine => {
+                if phase % 0.5 < 0.25 {
+                    sin(phase * TAU).abs()
+                } else {
+                    0.0
+                }
+            }
+            Waveform::Square => table_lookup(square_tables(), frequency_ratio, phase),
+            Waveform::Saw => table_lookup(saw_tables(), frequency_ratio, phase),
+            Waveform::Noise => noise,
+        }
+    }
+}
+
+const TABLE_SIZE: usize = 2048;
+const TABLE_OCTAVES: usize = 10;
+/// Highest fundamental of the first table as a fraction of the sample rate, each next
+/// table covers one octave higher. Tables are shared by every sample rate this way.
+const TABLE_BASE_RATIO: f32 = 1.0 / 1024.0;
+
+/// Harmonics of a table, as many as fit below Nyquist at the top of its octave.
+fn table_harmonics(octave: usize) -> usize {
+    let top_ratio = TABLE_BASE_RATIO * 2.0_f32.powi(octave as i32);
+    ((0.5 / top_ratio) as usize).clamp(1, TABLE_SIZE / 2)
+}
+
+/// Table for a fundamental given as a fraction of the sample rate.
+fn table_octave(frequency_ratio: f32) -> usize {
+    let octave = (frequency_ratio.abs() / TABLE_BASE_RATIO)
+        .max(1.0)
+        .log2()
+        .ceil() as usize;
+    octave.min(TABLE_OCTAVES - 1)
+}
+
+/// One additive table per octave, with every table keeping its harmonics below
+/// Nyquist for the highest fundamental of its octave.
+fn band_limited_tables(harmonic_amp: fn(usize) -> f32) -> Vec<Vec<f32>> {
+    (0..TABLE_OCTAVES)
+        .map(|octave| {
+            let harmonics = table_harmonics(octave);
+            (0..TABLE_SIZE)
+                .map(|i| {
+                    let phase = i as f32 / TABLE_SIZE as f32;
+                    (1..=harmonics)
+                        .map(|h| harmonic_amp(h) * (TAU * h as f32 * phase).sin())
+                        .sum()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn saw_tables() -> &'static Vec<Vec<f32>> {
+    static SAW_TABLES: OnceCell<Vec<Vec<f32>>> = OnceCell::new();
+    SAW_TABLES.get_or_init(|| band_limited_tables(|h| 2.0 / (PI * h as f32)))
+}
+
+fn square_tables() -> &'static Vec<Vec<f32>> {
+    static SQUARE_TABLES: OnceCell<Vec<Vec<f32>>> = OnceCell::new();
+    SQUARE_TABLES.get_or_init(|| {
+        band_limited_tables(|h| if h % 2 == 1 { 4.0 / (PI * h as f32) } else { 0.0 })
+    })
+}
+
+fn table_lookup(tables: &[Vec<f32>], frequency_ratio: f32, phase: f32) -> f32 {
+    let table = &tables[table_octave(frequency_ratio)];
+    let position = phase * TABLE_SIZE as f32;
+    let index = position as usize % TABLE_SIZE;
+    let fraction = position - position.floor();
+    let next = table[(index + 1) % TABLE_SIZE];
+    table[index] + (next - table[index]) * fraction
+}
+
+pub fn p_wave<T: Real>() -> An<PWave<T>> {
+    An(PWave::new(fundsp::DEFAULT_SR))
+}
+
+/// Phase modulated oscillator with a selectable waveform.
+/// - Input 0: frequency in Hz.
+/// - Input 1: Phase in decimal
+/// - Input 2: waveform index, see [`Waveform`]
+/// - Output 0: wave.
+#[derive(Default, Clone)]
+pub struct PWave<T: Real> {
+    phase: T,
+    sample_duration: T,
+    hash: u64,
+    noise_index: u64,
+}
+
+impl<T: Real> PWave<T> {
+    pub fn new(sample_rate: f64) -> Self {
+        // build the tables here so the audio thread never has to
+        saw_tables();
+        square_tables();
+        let mut wave = PWave::default();
+        wave.reset();
+        wave.set_sample_rate(sample_rate);
+        wave
+    }
+}
+
+impl<T: Real> AudioNode for PWave<T> {
+    const ID: u64 = 1340;
+    type Inputs = typenum::U3;
+    type Outputs = typenum::U1;
+
+    fn reset(&mut self) {
+        self.phase = T::from_f64(rnd1(self.hash));
+        self.noise_index = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_duration = convert(1.0 / sample_rate);
+    }
+
+    #[inline]
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        self.phase += T::from_f32(input[0]) * self.sample_duration;
+        self.phase -= self.phase.floor();
+        let phase = self.phase.to_f32() + input[1];
+        let phase = phase - phase.floor();
+        let waveform = Waveform::from_value(input[2]);
+        let noise = if waveform == Waveform::Noise {
+            self.noise_index = self.noise_index.wrapping_add(1);
+            (rnd1(self.hash ^ self.noise_index) * 2.0 - 1.0) as f32
+        } else {
+            0.0
+        };
+        let frequency_ratio = input[0] * self.sample_duration.to_f32();
+        [waveform.value(phase, frequency_ratio, noise)].into()
+    }
+
+    fn set_hash(&mut self, hash: u64) {
+        self.hash = hash;
+        self.reset();
+    }
+
+    fn route(&mut self, input: &SignalFrame, _frequency: f64) -> SignalFrame {
+        Routing::Arbitrary(0.0).route(input, self.outputs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() < tolerance,
+            "{value} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn sine_shapes() {
+        let shapes = [
+            (Waveform::Sine, [0.0, 1.0, 0.0, -1.0]),
+            (Waveform::HalfSine, [0.0, 1.0, 0.0, 0.0]),
+            (Waveform::AbsSine, [0.0, 1.0, 0.0, 1.0]),
+        ];
+        for (waveform, expected) in shapes {
+            for (quarter, value) in expected.iter().enumerate() {
+                assert_close(waveform.value(quarter as f32 / 4.0, 0.0, 0.0), *value, 1e-5);
+            }
+        }
+        let quarter = Waveform::QuarterSine;
+        assert_close(quarter.value(0.125, 0.0, 0.0), 0.5f32.sqrt(), 1e-5);
+        assert_eq!(quarter.value(0.375, 0.0, 0.0), 0.0);
+        assert_close(quarter.value(0.625, 0.0, 0.0), 0.5f32.sqrt(), 1e-5);
+        assert_eq!(quarter.value(0.875, 0.0, 0.0), 0.0);
+        assert_eq!(Waveform::Noise.value(0.3, 0.0, -0.7), -0.7);
+    }
+
+    #[test]
+    fn low_notes_use_the_full_tables() {
+        let ratio = 100.0 / 48000.0;
+        assert_close(Waveform::Square.value(0.25, ratio, 0.0), 1.0, 0.01);
+        assert_close(Waveform::Square.value(0.75, ratio, 0.0), -1.0, 0.01);
+        assert_close(Waveform::Saw.value(0.25, ratio, 0.0), 0.5, 0.01);
+        assert_close(Waveform::Saw.value(0.75, ratio, 0.0), -0.5, 0.01);
+    }
+
+    #[test]
+    fn tables_stay_below_nyquist() {
+        for ratio in [0.0001, 0.001, 0.003, 0.01, 0.05, 0.2, 0.49] {
+            let octave = table_octave(ratio);
+            assert!(table_harmonics(octave) as f32 * ratio <= 0.5, "{ratio}");
+        }
+        // only the fundamental is left close to Nyquist
+        assert_close(Waveform::Square.value(0.25, 0.4, 0.0), 4.0 / PI, 1e-3);
+    }
+
+    #[test]
+    fn tables_follow_the_sample_rate() {
+        let frequency = 5000.0;
+        let at_44k = table_octave(frequency / 44100.0);
+        let at_96k = table_octave(frequency / 96000.0);
+        assert!(at_96k < at_44k);
+        assert!(table_harmonics(at_44k) as f32 * frequency <= 22050.0);
+        assert!(table_harmonics(at_96k) as f32 * frequency <= 48000.0);
+    }
+}
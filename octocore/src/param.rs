@@ -1,15 +1,84 @@
+use crate::poly::MAX_VOICES;
 use fundsp::audionode::AudioNode;
 use fundsp::math::clamp;
 use fundsp::prelude::{shared, An, BufferMut, BufferRef, Shared};
-use fundsp::Frame;
+use fundsp::{full_simd_items, F32x, Frame};
+use std::sync::Arc;
 use typenum::{U0, U1};
 
+/// How the audio side follows changes of a `Param`, times are in seconds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Smoothing {
+    Off,
+    /// Exponential approach with the given time constant
+    OnePole(f32),
+    /// Straight line reaching every new value after the given time
+    Linear(f32),
+}
+
+/// What a value means, used to format it when no formatter is set.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Unit {
+    None,
+    Hz,
+    Db,
+    /// Seconds, shown in ms below one second
+    Seconds,
+    Ratio,
+    /// 0..1 shown as percent
+    Percent,
+    Cents,
+    Semitones,
+}
+
+/// Which readers of a `Param` a modulation offset applies to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ModTarget {
+    /// Every reader
+    Global,
+    /// Readers outside of the voices, standing in for the offsets of per voice sources
+    Summary,
+    /// A single voice
+    Voice(usize),
+}
+
+const MOD_TARGETS: usize = 2 + MAX_VOICES;
+
+impl ModTarget {
+    fn index(&self) -> usize {
+        match self {
+            ModTarget::Global => 0,
+            ModTarget::Summary => 1,
+            ModTarget::Voice(voice) => 2 + voice,
+        }
+    }
+}
+
+/// Mapping between a value and its normalized 0..1 position, see [`Param::normalized`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// Positions are raised to this power, above 1 gives the low end more travel
+    Skew(f32),
+    /// Same travel for every doubling of the value, needs a positive range
+    Log,
+}
+
 #[derive(Clone)]
 pub struct Param {
     value: Shared,
     clamp: (f32, f32),
     process: Option<(fn(value: f32) -> f32)>,
-    modulation: Shared,
+    smoothing: Smoothing,
+    name: &'static str,
+    unit: Unit,
+    curve: Curve,
+    default: f32,
+    /// Values snap to multiples of the step when read
+    step: Option<f32>,
+    formatter: Option<fn(value: f32) -> String>,
+    /// Offsets of all modulation slots summed per [`ModTarget`], see [`Param::voice_value`]
+    modulation: Arc<Vec<Shared>>,
 }
 
 impl Param {
@@ -18,10 +87,126 @@ impl Param {
             value: shared(value),
             clamp,
             process,
-            modulation: shared(0.0),
+            smoothing: Smoothing::Off,
+            name: "",
+            unit: Unit::None,
+            curve: Curve::Linear,
+            default: value,
+            step: None,
+            formatter: None,
+            modulation: Arc::new((0..MOD_TARGETS).map(|_| shared(0.0)).collect()),
         }
     }
 
+    /// Smooths the value in every [`ParamVar`] reading this param, against zipper noise.
+    pub fn with_smoothing(mut self, smoothing: Smoothing) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn with_unit(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    pub fn with_curve(mut self, curve: Curve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    pub fn with_step(mut self, step: f32) -> Self {
+        self.step = Some(step);
+        self
+    }
+
+    /// Replaces the formatting by unit, e.g. to show the name of an enum variant.
+    pub fn with_formatter(mut self, formatter: fn(value: f32) -> String) -> Self {
+        self.formatter = Some(formatter);
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    pub fn step(&self) -> Option<f32> {
+        self.step
+    }
+
+    pub fn default_value(&self) -> f32 {
+        self.default
+    }
+
+    pub fn reset(&self) {
+        self.set_value(self.default)
+    }
+
+    /// Position of `value` along the curve, 0 at the lowest and 1 at the highest value.
+    pub fn normalize(&self, value: f32) -> f32 {
+        let (min, max) = self.clamp;
+        let value = clamp(min, max, value);
+        match self.curve {
+            Curve::Linear => (value - min) / (max - min),
+            Curve::Skew(power) => ((value - min) / (max - min)).powf(1.0 / power),
+            Curve::Log => (value / min).ln() / (max / min).ln(),
+        }
+    }
+
+    pub fn denormalize(&self, position: f32) -> f32 {
+        let (min, max) = self.clamp;
+        let position = clamp(0.0, 1.0, position);
+        match self.curve {
+            Curve::Linear => min + position * (max - min),
+            Curve::Skew(power) => min + position.powf(power) * (max - min),
+            Curve::Log => min * (max / min).powf(position),
+        }
+    }
+
+    /// Normalized position of the unmodulated value.
+    pub fn normalized(&self) -> f32 {
+        self.normalize(self.unmodulated_value())
+    }
+
+    pub fn set_normalized(&self, position: f32) {
+        self.set_value(self.denormalize(position))
+    }
+
+    pub fn format(&self, value: f32) -> String {
+        if let Some(formatter) = self.formatter {
+            return formatter(value);
+        }
+        let number = match self.step {
+            Some(step) if step >= 1.0 => format!("{value:.0}"),
+            _ => format!("{value:.2}"),
+        };
+        match self.unit {
+            Unit::None => number,
+            Unit::Hz if value >= 1000.0 => format!("{:.2} kHz", value / 1000.0),
+            Unit::Hz => format!("{number} Hz"),
+            Unit::Db => format!("{value:.1} dB"),
+            Unit::Seconds if value < 1.0 => format!("{:.0} ms", value * 1000.0),
+            Unit::Seconds => format!("{value:.2} s"),
+            Unit::Ratio => format!("x{number}"),
+            Unit::Percent => format!("{:.0}%", value * 100.0),
+            Unit::Cents => format!("{number} ct"),
+            Unit::Semitones => format!("{number} st"),
+        }
+    }
+
+    /// Current value formatted for the display, before `process`.
+    pub fn display(&self) -> String {
+        self.format(self.raw_value())
+    }
+
     pub fn set_value(&self, value: f32) {
         self.value
             .set_value(clamp(self.clamp.0, self.clamp.1, value))
@@ -31,30 +216,221 @@ impl Param {
         self.value.value()
     }
 
-    pub fn set_modulation(&self, value: f32) {
-        self.modulation.set_value(value)
+    /// Adds to the summed offset of `target`. Offsets are only written by the audio thread,
+    /// see [`ParamSink`].
+    pub fn add_modulation(&self, target: ModTarget, delta: f32) {
+        let offset = &self.modulation[target.index()];
+        offset.set_value(offset.value() + delta)
     }
 
+    pub fn modulation(&self, target: ModTarget) -> f32 {
+        self.modulation[target.index()].value()
+    }
+
+    /// Summed offset seen by one voice, or by the readers outside of the voices for `None`.
+    fn offset(&self, voice: Option<usize>) -> f32 {
+        let own = match voice {
+            Some(voice) => ModTarget::Voice(voice),
+            None => ModTarget::Summary,
+        };
+        self.modulation(ModTarget::Global) + self.modulation(own)
+    }
+
+    /// Distance between the lowest and the highest value.
+    pub fn range(&self) -> f32 {
+        self.clamp.1 - self.clamp.0
+    }
+
+    /// Clamps a modulated value and snaps it to the step.
+    fn resolve(&self, value: f32) -> f32 {
+        let value = clamp(self.clamp.0, self.clamp.1, value);
+        match self.step {
+            Some(step) => clamp(self.clamp.0, self.clamp.1, (value / step).round() * step),
+            None => value,
+        }
+    }
+
+    fn apply_process(&self, value: f32) -> f32 {
+        match self.process {
+            Some(process) => process(value),
+            None => value,
+        }
+    }
+
+    /// Resolves a modulated value and maps it by `process`.
+    fn finish(&self, value: f32) -> f32 {
+        self.apply_process(self.resolve(value))
+    }
+
+    /// Edited value snapped to the step, what [`ParamVar`] smooths towards.
+    fn stepped_value(&self) -> f32 {
+        self.resolve(self.value.value())
+    }
+
+    /// Modulated value around `smoothed` on its way to the stepped `target`. Only the
+    /// settled value is snapped, so stepped params still pass through the values between.
+    fn finish_smoothed(&self, smoothed: f32, target: f32, offset: f32) -> f32 {
+        if smoothed == target {
+            return self.finish(target + offset);
+        }
+        let modulation = self.resolve(target + offset) - target;
+        self.apply_process(clamp(self.clamp.0, self.clamp.1, smoothed + modulation))
+    }
+
+    /// Modulated and clamped value before `process`, in the unit the param is edited in.
+    pub fn raw_value(&self) -> f32 {
+        self.resolve(self.value.value() + self.offset(None))
+    }
+
+    /// Modulated value, clamped to the range and then mapped by `process`.
+    /// The result is not clamped again, e.g. a dB range maps to gains.
     pub fn value(&self) -> f32 {
-        clamp(
-            self.clamp.0,
-            self.clamp.1,
-            self.value.value() + self.modulation.value(),
-        )
+        self.apply_process(self.raw_value())
+    }
+
+    /// Value as seen by one voice, with the global offset and the offset of that voice.
+    pub fn voice_value(&self, voice: usize) -> f32 {
+        self.finish(self.value.value() + self.offset(Some(voice)))
+    }
+
+    /// [`Param::voice_value`] of `voice`, or [`Param::value`] outside of the voices.
+    pub fn value_for(&self, voice: Option<usize>) -> f32 {
+        match voice {
+            Some(voice) => self.voice_value(voice),
+            None => self.value(),
+        }
+    }
+}
+
+/// What the sinks of one modulation slot currently add to their `Param`, one value per
+/// [`ModTarget`]. Lets a slot take its offsets back once it is routed elsewhere.
+#[derive(Clone)]
+pub struct Contributions {
+    values: Arc<Vec<Shared>>,
+    /// Set once the offsets were taken back, see [`Contributions::withdraw`]
+    withdrawn: Shared,
+}
+
+impl Default for Contributions {
+    fn default() -> Self {
+        Self {
+            values: Arc::new((0..MOD_TARGETS).map(|_| shared(0.0)).collect()),
+            withdrawn: shared(0.0),
+        }
+    }
+}
+
+impl Contributions {
+    /// Voice offset furthest from 0, so global readers follow e.g. the hardest pressed key.
+    pub fn strongest_voice(&self) -> f32 {
+        self.values[ModTarget::Voice(0).index()..]
+            .iter()
+            .map(|value| value.value())
+            .fold(0.0, |strongest, value| {
+                if value.abs() > strongest.abs() {
+                    value
+                } else {
+                    strongest
+                }
+            })
+    }
+
+    /// Subtracts every offset from `param` and starts over at 0. Only the audio thread
+    /// may call this, the sinks writing into `param` could still be running otherwise.
+    pub fn withdraw(&self, param: &Param) {
+        for (offset, value) in param.modulation.iter().zip(self.values.iter()) {
+            offset.set_value(offset.value() - value.value());
+            value.set_value(0.0);
+        }
+        self.withdrawn.set_value(1.0);
+    }
+
+    pub fn is_withdrawn(&self) -> bool {
+        self.withdrawn.value() > 0.5
+    }
+}
+
+/// Transforms for [`Param::new`], applied to the clamped value on every read.
+pub mod process {
+    /// Decibels to a linear gain.
+    pub fn db_to_gain(db: f32) -> f32 {
+        fundsp::math::db_amp(db)
+    }
+
+    /// Semitones to a frequency ratio.
+    pub fn semitones_to_ratio(semitones: f32) -> f32 {
+        fundsp::math::semitone_ratio(semitones)
+    }
+
+    /// Cents to a frequency ratio.
+    pub fn cents_to_ratio(cents: f32) -> f32 {
+        fundsp::math::semitone_ratio(cents / 100.0)
+    }
+
+    /// Nearest integer, e.g. for harmonic ratios.
+    pub fn quantize(value: f32) -> f32 {
+        value.round()
     }
 }
 
-/// Outputs the value of a shared variable.
+/// Outputs the value of a shared variable, smoothed as configured on the `Param`.
+/// Only the edited value is smoothed, modulation is added on top so it moves right away.
+/// A voice node also adds the modulation of its voice.
+/// Block processing reads the `Param` once per block.
 #[derive(Clone)]
 pub struct ParamVar {
     param: Param,
+    voice: Option<usize>,
+    sample_rate: f32,
+    /// Per sample factor of [`Smoothing::OnePole`]
+    coefficient: f32,
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: u32,
 }
 
 impl ParamVar {
-    pub fn new(param: &Param) -> Self {
-        Self {
+    pub fn new(param: &Param, voice: Option<usize>) -> Self {
+        let mut var = Self {
             param: param.clone(),
+            voice,
+            sample_rate: 0.0,
+            coefficient: 0.0,
+            current: 0.0,
+            target: 0.0,
+            step: 0.0,
+            remaining: 0,
+        };
+        var.reset();
+        var.set_sample_rate(fundsp::DEFAULT_SR);
+        var
+    }
+
+    /// Moves the output one sample towards `target`.
+    fn next(&mut self, target: f32) -> f32 {
+        match self.param.smoothing {
+            Smoothing::Off => self.current = target,
+            Smoothing::OnePole(_) => {
+                self.current = target + (self.current - target) * self.coefficient;
+            }
+            Smoothing::Linear(time) => {
+                if target != self.target {
+                    self.target = target;
+                    self.remaining = ((time * self.sample_rate).round() as u32).max(1);
+                    self.step = (target - self.current) / self.remaining as f32;
+                }
+                if self.remaining > 0 {
+                    self.remaining -= 1;
+                    self.current = if self.remaining == 0 {
+                        target
+                    } else {
+                        self.current + self.step
+                    };
+                }
+            }
         }
+        self.current
     }
 
     /// Set the value of this variable.
@@ -64,7 +440,7 @@ impl ParamVar {
 
     /// Get the value of this variable.
     pub fn value(&self) -> f32 {
-        self.param.value()
+        self.param.value_for(self.voice)
     }
 }
 
@@ -74,34 +450,83 @@ impl AudioNode for ParamVar {
     type Inputs = U0;
     type Outputs = U1;
 
+    fn reset(&mut self) {
+        self.current = self.param.stepped_value();
+        self.target = self.current;
+        self.remaining = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate as f32;
+        self.coefficient = match self.param.smoothing {
+            Smoothing::OnePole(time) if time > 0.0 => (-1.0 / (time * self.sample_rate)).exp(),
+            _ => 0.0,
+        };
+    }
+
     #[inline]
     fn tick(&mut self, _: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
-        let sample: f32 = self.value();
-        [sample].into()
+        let target = self.param.stepped_value();
+        let smoothed = self.next(target);
+        let offset = self.param.offset(self.voice);
+        [self.param.finish_smoothed(smoothed, target, offset)].into()
     }
 
     fn process(&mut self, size: usize, _input: &BufferRef, output: &mut BufferMut) {
-        let sample = self.value();
-        output.set(0, size, sample.into());
+        let target = self.param.stepped_value();
+        let offset = self.param.offset(self.voice);
+        match self.param.smoothing {
+            Smoothing::Off => {
+                let smoothed = self.next(target);
+                let sample = F32x::splat(self.param.finish_smoothed(smoothed, target, offset));
+                for i in 0..full_simd_items(size) {
+                    output.set(0, i, sample);
+                }
+            }
+            _ => {
+                for i in 0..size {
+                    let smoothed = self.next(target);
+                    output.set_f32(0, i, self.param.finish_smoothed(smoothed, target, offset));
+                }
+            }
+        }
     }
 }
 
 pub fn param(param: &Param) -> An<ParamVar> {
-    An(ParamVar::new(param))
+    An(ParamVar::new(param, None))
+}
+
+/// Value of a `Param` inside the graph of one voice.
+pub fn voice_param(param: &Param, voice: usize) -> An<ParamVar> {
+    An(ParamVar::new(param, Some(voice)))
 }
 
+/// Makes its input the contribution of one modulation slot to a `Param` offset.
+/// Only the change since the last write is added, so every slot writing into the same
+/// offset keeps its share and reading stays one value per target.
 #[derive(Clone)]
 pub struct ParamSink {
     param: Param,
+    target: ModTarget,
+    contribution: Shared,
 }
 
 impl ParamSink {
-    pub fn new(param: &Param) -> Self {
+    pub fn new(param: &Param, target: ModTarget, contributions: &Contributions) -> Self {
         Self {
             param: param.clone(),
+            target,
+            contribution: contributions.values[target.index()].clone(),
         }
     }
 
+    fn write(&self, value: f32) {
+        let delta = value - self.contribution.value();
+        self.param.add_modulation(self.target, delta);
+        self.contribution.set_value(value);
+    }
+
     pub fn set_value(&self, value: f32) {
         self.param.set_value(value)
     }
@@ -119,16 +544,371 @@ impl AudioNode for ParamSink {
 
     #[inline]
     fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
-        self.param.set_modulation(input[0]);
+        self.write(input[0]);
         Frame::default()
     }
 
-    fn process(&mut self, _size: usize, input: &BufferRef, _output: &mut BufferMut) {
-        //self.param.set_modulation(input.at(0, 0));
-        ()
+    fn process(&mut self, size: usize, input: &BufferRef, _output: &mut BufferMut) {
+        if size == 0 {
+            return;
+        }
+        self.write(input.at_f32(0, 0));
     }
 }
 
-pub fn param_sink(param: &Param) -> An<ParamSink> {
-    An(ParamSink::new(param))
+/// Block processing hands over only the first sample of each block, so modulation runs at
+/// the block rate and anything faster than a block is lost. Ticking writes every sample.
+/// Readers also take the value once per block, see [`ParamVar`].
+pub fn param_sink(
+    param: &Param,
+    target: ModTarget,
+    contributions: &Contributions,
+) -> An<ParamSink> {
+    An(ParamSink::new(param, target, contributions))
+}
+
+/// Takes back what retired modulation slots added to their `Param`s, once it first runs.
+/// Runs on the audio thread, so the retired sinks are no longer writing by then.
+#[derive(Clone)]
+pub struct ParamRelease {
+    retired: Vec<(Param, Contributions)>,
+    released: bool,
+}
+
+impl ParamRelease {
+    pub fn new(retired: Vec<(Param, Contributions)>) -> Self {
+        Self {
+            retired,
+            released: false,
+        }
+    }
+
+    fn release(&mut self) {
+        if !self.released {
+            for (param, contributions) in self.retired.iter() {
+                contributions.withdraw(param);
+            }
+            self.released = true;
+        }
+    }
+}
+
+impl AudioNode for ParamRelease {
+    const ID: u64 = 1344;
+
+    type Inputs = U0;
+    type Outputs = U0;
+
+    #[inline]
+    fn tick(&mut self, _: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        self.release();
+        Frame::default()
+    }
+
+    fn process(&mut self, _size: usize, _input: &BufferRef, _output: &mut BufferMut) {
+        self.release();
+    }
+}
+
+pub fn param_release(retired: Vec<(Param, Contributions)>) -> An<ParamRelease> {
+    An(ParamRelease::new(retired))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lfo::{lfo, Lfo, LfoParams};
+    use fundsp::prelude::{constant, map, BufferVec};
+
+    const BLOCK: usize = 64;
+
+    fn test_lfo() -> An<Lfo> {
+        let params = LfoParams::default();
+        params.rate.set_value(20.0);
+        lfo(
+            &params,
+            &Param::new(120.0, (20.0, 300.0), None),
+            vec![],
+            0,
+            None,
+        )
+    }
+
+    /// LFO -> sink -> `Param` -> `ParamVar`, the way a slot and a voice share a `Param`.
+    struct Chain {
+        target: Param,
+        lfo: An<Lfo>,
+        sink: An<ParamSink>,
+        var: An<ParamVar>,
+    }
+
+    impl Chain {
+        fn new(smoothing: Smoothing) -> Self {
+            let target = Param::new(0.5, (0.0, 1.0), None).with_smoothing(smoothing);
+            let sink = param_sink(&target, ModTarget::Global, &Contributions::default());
+            let var = param(&target);
+            Self {
+                target,
+                lfo: test_lfo(),
+                sink,
+                var,
+            }
+        }
+    }
+
+    #[test]
+    fn modulation_chain_process_matches_tick() {
+        let no_input = BufferVec::new(0);
+        let mut no_output = BufferVec::new(0);
+        let mut lfo_output = BufferVec::new(1);
+        let mut output = BufferVec::new(1);
+        for smoothing in [
+            Smoothing::Off,
+            Smoothing::Linear(0.001),
+            Smoothing::OnePole(0.001),
+        ] {
+            let mut ticked = Chain::new(smoothing);
+            let mut processed = Chain::new(smoothing);
+            for block in 0..8 {
+                let base = 0.25 + 0.05 * block as f32;
+                ticked.target.set_value(base);
+                processed.target.set_value(base);
+
+                // a slot node runs before the voice node reading the Param,
+                // processing hands over the first sample of the block
+                let first = ticked.lfo.tick(&Frame::default())[0];
+                for _ in 1..BLOCK {
+                    ticked.lfo.tick(&Frame::default());
+                }
+                ticked.sink.tick(&[first].into());
+                processed
+                    .lfo
+                    .process(BLOCK, &no_input.buffer_ref(), &mut lfo_output.buffer_mut());
+                processed.sink.process(
+                    BLOCK,
+                    &lfo_output.buffer_ref(),
+                    &mut no_output.buffer_mut(),
+                );
+                assert_ne!(processed.target.modulation(ModTarget::Global), 0.0);
+
+                processed
+                    .var
+                    .process(BLOCK, &no_input.buffer_ref(), &mut output.buffer_mut());
+                for i in 0..BLOCK {
+                    let sample = ticked.var.tick(&Frame::default())[0];
+                    assert_close(output.buffer_ref().at_f32(0, i), sample);
+                }
+            }
+        }
+    }
+
+    fn run(var: &mut An<ParamVar>, ticks: usize) -> f32 {
+        let mut value = 0.0;
+        for _ in 0..ticks {
+            value = var.tick(&Frame::default())[0];
+        }
+        value
+    }
+
+    #[test]
+    fn linear_smoothing_ramps_after_a_step() {
+        let target = Param::new(0.0, (0.0, 1.0), None).with_smoothing(Smoothing::Linear(0.01));
+        let mut var = param(&target);
+        var.set_sample_rate(1000.0);
+        assert_eq!(run(&mut var, 1), 0.0);
+
+        target.set_value(1.0);
+        for i in 1..10 {
+            assert!((run(&mut var, 1) - i as f32 / 10.0).abs() < 1e-5);
+        }
+        assert_eq!(run(&mut var, 1), 1.0);
+        assert_eq!(run(&mut var, 10), 1.0);
+    }
+
+    #[test]
+    fn modulation_skips_the_smoothing() {
+        let target = Param::new(0.25, (0.0, 1.0), None).with_smoothing(Smoothing::Linear(0.01));
+        let mut var = param(&target);
+        var.set_sample_rate(1000.0);
+        for i in 1..=20 {
+            target.add_modulation(ModTarget::Global, 0.01);
+            assert_close(run(&mut var, 1), 0.25 + 0.01 * i as f32);
+        }
+
+        // the edited value still ramps below the modulation
+        target.set_value(0.5);
+        assert_close(run(&mut var, 1), 0.275 + 0.2);
+        assert_close(run(&mut var, 9), 0.5 + 0.2);
+    }
+
+    #[test]
+    fn stepped_params_ramp_between_steps() {
+        let ratio = Param::new(1.0, (0.0, 31.0), None)
+            .with_step(1.0)
+            .with_smoothing(Smoothing::Linear(0.01));
+        let mut var = param(&ratio);
+        var.set_sample_rate(1000.0);
+        assert_eq!(run(&mut var, 1), 1.0);
+
+        ratio.set_value(3.0);
+        assert_close(run(&mut var, 1), 1.2);
+        assert_close(run(&mut var, 4), 2.0);
+        assert_eq!(run(&mut var, 5), 3.0);
+
+        // only the target snaps to the step
+        ratio.set_value(2.4);
+        assert_close(run(&mut var, 1), 2.9);
+        assert_eq!(run(&mut var, 9), 2.0);
+        ratio.add_modulation(ModTarget::Global, 1.4);
+        assert_eq!(run(&mut var, 1), 3.0);
+    }
+
+    #[test]
+    fn one_pole_smoothing_approaches_a_step() {
+        let target = Param::new(0.0, (0.0, 1.0), None).with_smoothing(Smoothing::OnePole(0.01));
+        let mut var = param(&target);
+        var.set_sample_rate(1000.0);
+        target.set_value(1.0);
+        let first = run(&mut var, 1);
+        assert!(first > 0.0 && first < 0.1);
+        // one time constant covers 1 - 1/e of the step
+        assert!((run(&mut var, 9) - (1.0 - (-1.0f32).exp())).abs() < 1e-4);
+        assert!(run(&mut var, 100) > 0.9999);
+    }
+
+    #[test]
+    fn normalized_positions_follow_the_curve() {
+        let linear = Param::new(0.0, (-1.0, 1.0), None);
+        assert_eq!(linear.normalize(0.0), 0.5);
+        assert_eq!(linear.denormalize(0.25), -0.5);
+
+        let skewed = Param::new(0.0, (0.0, 4.0), None).with_curve(Curve::Skew(2.0));
+        assert_eq!(skewed.normalize(1.0), 0.5);
+        assert_eq!(skewed.denormalize(0.5), 1.0);
+
+        let log = Param::new(100.0, (10.0, 1000.0), None).with_curve(Curve::Log);
+        assert!((log.normalized() - 0.5).abs() < 1e-6);
+        log.set_normalized(1.0);
+        assert!((log.value() - 1000.0).abs() < 1e-2);
+        assert_eq!(log.normalize(1.0), 0.0);
+    }
+
+    #[test]
+    fn steps_and_formatting() {
+        let ratio = Param::new(1.0, (0.0, 31.0), None)
+            .with_step(1.0)
+            .with_unit(Unit::Ratio);
+        ratio.set_value(2.4);
+        assert_eq!(ratio.value(), 2.0);
+        assert_eq!(ratio.unmodulated_value(), 2.4);
+        assert_eq!(ratio.display(), "x2");
+        ratio.reset();
+        assert_eq!(ratio.value(), 1.0);
+
+        let time = Param::new(0.25, (0.0, 5.0), None).with_unit(Unit::Seconds);
+        assert_eq!(time.display(), "250 ms");
+        time.set_value(1.5);
+        assert_eq!(time.display(), "1.50 s");
+
+        let level = Param::new(0.5, (0.0, 1.0), None).with_unit(Unit::Percent);
+        assert_eq!(level.display(), "50%");
+        let named = level.with_formatter(|value| format!("level {value}"));
+        assert_eq!(named.display(), "level 0.5");
+    }
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!(
+            (value - expected).abs() < 1e-4,
+            "{value} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn process_maps_the_modulated_value() {
+        let gain = Param::new(-6.0, (-60.0, 0.0), Some(process::db_to_gain)).with_unit(Unit::Db);
+        assert_close(gain.value(), 0.501187);
+        assert_eq!(gain.display(), "-6.0 dB");
+        gain.add_modulation(ModTarget::Global, 6.0);
+        assert_close(gain.value(), 1.0);
+        gain.add_modulation(ModTarget::Voice(1), -20.0);
+        assert_close(gain.voice_value(1), 0.1);
+
+        let pitch = Param::new(12.0, (-24.0, 24.0), Some(process::semitones_to_ratio));
+        assert_close(pitch.value(), 2.0);
+        let detune = Param::new(-1200.0, (-1200.0, 1200.0), Some(process::cents_to_ratio));
+        assert_close(detune.value(), 0.5);
+        let ratio = Param::new(2.6, (0.5, 16.0), Some(process::quantize));
+        assert_eq!(ratio.value(), 3.0);
+    }
+
+    #[test]
+    fn clamps_before_the_transform() {
+        let gain = Param::new(0.0, (-60.0, 0.0), Some(process::db_to_gain));
+        gain.set_value(12.0);
+        assert_close(gain.value(), 1.0);
+        gain.add_modulation(ModTarget::Global, -100.0);
+        assert_close(gain.value(), 0.001);
+        gain.add_modulation(ModTarget::Global, 200.0);
+        assert_close(gain.voice_value(0), 1.0);
+
+        let ratio = Param::new(0.0, (0.5, 16.0), Some(process::quantize));
+        ratio.add_modulation(ModTarget::Global, -10.0);
+        assert_eq!(ratio.value(), 1.0);
+    }
+
+    #[test]
+    fn transformed_values_are_not_clamped_again() {
+        // a gain of 1 lies outside the dB range
+        let gain = Param::new(0.0, (-60.0, 0.0), Some(process::db_to_gain));
+        assert_close(gain.value(), 1.0);
+        assert_eq!(gain.raw_value(), 0.0);
+
+        let pitch = Param::new(-24.0, (-24.0, 24.0), Some(process::semitones_to_ratio));
+        pitch.add_modulation(ModTarget::Global, -12.0);
+        assert_close(pitch.value(), 0.25);
+        assert_close(pitch.voice_value(3), 0.25);
+
+        let smoothed = ParamVar::new(&gain, None);
+        assert_close(smoothed.value(), 1.0);
+    }
+
+    #[test]
+    fn voice_modulation_stays_in_its_voice() {
+        let param = Param::new(0.5, (0.0, 1.0), None);
+        let (first, second) = (Contributions::default(), Contributions::default());
+        let mut sinks = (constant(0.1) >> param_sink(&param, ModTarget::Global, &first))
+            | (constant(0.2) >> param_sink(&param, ModTarget::Voice(2), &first))
+            | (constant(0.1) >> param_sink(&param, ModTarget::Voice(2), &second));
+        // writing the same value again changes nothing
+        for _ in 0..4 {
+            sinks.tick(&Frame::default());
+        }
+        assert_close(param.value(), 0.6);
+        assert_close(param.voice_value(1), 0.6);
+        assert_close(param.voice_value(2), 0.9);
+
+        first.withdraw(&param);
+        assert_close(param.value(), 0.5);
+        assert_close(param.voice_value(1), 0.5);
+        assert_close(param.voice_value(2), 0.6);
+    }
+
+    #[test]
+    fn global_readers_follow_the_strongest_voice() {
+        let param = Param::new(0.0, (-1.0, 1.0), None);
+        let slot = Contributions::default();
+        let mut sinks = (constant(0.2) >> param_sink(&param, ModTarget::Voice(1), &slot))
+            | (constant(-0.3) >> param_sink(&param, ModTarget::Voice(3), &slot));
+        sinks.tick(&Frame::default());
+        assert_close(slot.strongest_voice(), -0.3);
+
+        let summary = slot.clone();
+        let mut summary_sink = map(move |_: &Frame<f32, U0>| summary.strongest_voice())
+            >> param_sink(&param, ModTarget::Summary, &slot);
+        summary_sink.tick(&Frame::default());
+        assert_close(param.value(), -0.3);
+        // voices only see their own offset
+        assert_close(param.voice_value(1), 0.2);
+        assert_close(param.voice_value(0), 0.0);
+    }
 }
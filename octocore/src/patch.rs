@@ -0,0 +1,367 @@
+use crate::algorithm::Algorithm;
+use crate::lfo::LfoParams;
+use crate::modulation::{create_modulation_list, ModSource};
+use crate::param::Param;
+use crate::poly::{NotePriority, VoiceMode};
+use crate::synth_params::{AdsrParams, DxEnvParams, OpParams, SynthParams, VoiceSettings};
+use anyhow::bail;
+use std::fmt::Debug;
+use std::path::Path;
+use strum::IntoEnumIterator;
+use toml::{Table, Value};
+
+/// Written into every patch, patches with a higher version are refused.
+pub const PATCH_VERSION: i64 = 1;
+
+// Field tables of the patch sections, the modulation destinations are built from them too.
+
+pub fn op_fields(op: &OpParams) -> [(&'static str, &Param); 16] {
+    [
+        ("ratio", &op.ratio),
+        ("fine", &op.fine),
+        ("detune", &op.detune),
+        ("freq_mode", &op.freq_mode),
+        ("fixed_freq", &op.fixed_freq),
+        ("volume", &op.volume),
+        ("feedback", &op.feedback),
+        ("waveform", &op.waveform),
+        ("velocity_sens", &op.velocity_sens),
+        ("key_breakpoint", &op.key_breakpoint),
+        ("key_left_depth", &op.key_left_depth),
+        ("key_right_depth", &op.key_right_depth),
+        ("key_left_curve", &op.key_left_curve),
+        ("key_right_curve", &op.key_right_curve),
+        ("key_rate_scaling", &op.key_rate_scaling),
+        ("env_kind", &op.env_kind),
+    ]
+}
+
+pub fn adsr_fields(adsr: &AdsrParams) -> [(&'static str, &Param); 13] {
+    [
+        ("attack", &adsr.a),
+        ("decay", &adsr.d),
+        ("sustain", &adsr.s),
+        ("release", &adsr.r),
+        ("delay", &adsr.delay),
+        ("hold", &adsr.hold),
+        ("attack_curve", &adsr.a_curve),
+        ("decay_curve", &adsr.d_curve),
+        ("release_curve", &adsr.r_curve),
+        ("mode", &adsr.mode),
+        ("loop_mode", &adsr.loop_mode),
+        ("loop_start", &adsr.loop_start),
+        ("loop_end", &adsr.loop_end),
+    ]
+}
+
+pub fn dx_fields(dx: &DxEnvParams) -> [(&'static str, &Param); 8] {
+    [
+        ("r1", &dx.r1),
+        ("r2", &dx.r2),
+        ("r3", &dx.r3),
+        ("r4", &dx.r4),
+        ("l1", &dx.l1),
+        ("l2", &dx.l2),
+        ("l3", &dx.l3),
+        ("l4", &dx.l4),
+    ]
+}
+
+pub fn lfo_fields(lfo: &LfoParams) -> [(&'static str, &Param); 8] {
+    [
+        ("shape", &lfo.shape),
+        ("rate", &lfo.rate),
+        ("sync", &lfo.sync),
+        ("depth", &lfo.depth),
+        ("phase", &lfo.phase),
+        ("fade", &lfo.fade),
+        ("retrigger", &lfo.retrigger),
+        ("per_voice", &lfo.per_voice),
+    ]
+}
+
+pub fn voice_fields(settings: &VoiceSettings) -> [(&'static str, &Param); 6] {
+    [
+        ("glide", &settings.glide),
+        ("unison", &settings.unison),
+        ("unison_detune", &settings.unison_detune),
+        ("unison_spread", &settings.unison_spread),
+        ("bend_up", &settings.bend_up),
+        ("bend_down", &settings.bend_down),
+    ]
+}
+
+fn write_params(fields: &[(&'static str, &Param)]) -> Table {
+    fields
+        .iter()
+        .map(|(key, param)| {
+            (
+                key.to_string(),
+                Value::Float(param.unmodulated_value() as f64),
+            )
+        })
+        .collect()
+}
+
+fn number(table: Option<&Table>, key: &str) -> Option<f32> {
+    let value = table?.get(key)?;
+    value
+        .as_float()
+        .or_else(|| value.as_integer().map(|value| value as f64))
+        .map(|value| value as f32)
+}
+
+/// Sets every param found in `table`, the others go back to their default.
+fn read_params(table: Option<&Table>, fields: &[(&'static str, &Param)]) {
+    for (key, param) in fields {
+        match number(table, key) {
+            Some(value) => param.set_value(value),
+            None => param.reset(),
+        }
+    }
+}
+
+/// Enum variants are stored by their Rust name, which stays stable when display names change.
+fn write_choice<T: Debug>(choice: T) -> Value {
+    Value::String(format!("{choice:?}"))
+}
+
+fn read_choice<T: IntoEnumIterator + Debug>(table: Option<&Table>, key: &str) -> Option<T> {
+    let name = table?.get(key)?.as_str()?;
+    T::iter().find(|choice| format!("{choice:?}") == name)
+}
+
+fn section<'a>(table: &'a Table, key: &str) -> Option<&'a Table> {
+    table.get(key)?.as_table()
+}
+
+/// Table `index` of an array of tables.
+fn entry<'a>(table: &'a Table, key: &str, index: usize) -> Option<&'a Table> {
+    table.get(key)?.as_array()?.get(index)?.as_table()
+}
+
+impl SynthParams {
+    /// Operator, envelope, algorithm, voice, LFO and modulation matrix settings as TOML.
+    pub fn to_patch(&self) -> String {
+        let mut patch = Table::new();
+        patch.insert("version".into(), Value::Integer(PATCH_VERSION));
+        patch.insert("algorithm".into(), write_choice(self.algorithm()));
+        patch.insert(
+            "tempo".into(),
+            Value::Float(self.tempo.unmodulated_value() as f64),
+        );
+
+        let mut voice = write_params(&voice_fields(&self.voice_settings));
+        voice.insert(
+            "mode".into(),
+            write_choice(*self.voice_settings.mode.lock().unwrap()),
+        );
+        voice.insert(
+            "priority".into(),
+            write_choice(*self.voice_settings.priority.lock().unwrap()),
+        );
+        patch.insert("voice".into(), Value::Table(voice));
+
+        let ops = self.ops.iter().map(|op| {
+            let mut table = write_params(&op_fields(op));
+            table.insert(
+                "adsr".into(),
+                Value::Table(write_params(&adsr_fields(&op.adsr_params))),
+            );
+            table.insert(
+                "dx".into(),
+                Value::Table(write_params(&dx_fields(&op.dx_params))),
+            );
+            Value::Table(table)
+        });
+        patch.insert("ops".into(), Value::Array(ops.collect()));
+
+        let lfos = self
+            .lfos
+            .iter()
+            .map(|lfo| Value::Table(write_params(&lfo_fields(lfo))));
+        patch.insert("lfos".into(), Value::Array(lfos.collect()));
+
+        let dests = create_modulation_list(self);
+        let slots = self.mod_slots.iter().map(|slot| {
+            let mut table = Table::new();
+            table.insert("source".into(), write_choice(slot.source()));
+            table.insert(
+                "destination".into(),
+                Value::String(dests[slot.destination()].1.key.clone()),
+            );
+            table.insert(
+                "amount".into(),
+                Value::Float(slot.amount.unmodulated_value() as f64),
+            );
+            Value::Table(table)
+        });
+        patch.insert("mod_slots".into(), Value::Array(slots.collect()));
+
+        patch.to_string()
+    }
+
+    /// Applies a patch written by [`SynthParams::to_patch`]. Unknown fields are ignored and
+    /// missing ones are reset to their default, so patches of older versions keep loading.
+    /// The sound has to be rebuilt afterwards for the algorithm and modulation slots to apply.
+    pub fn load_patch(&self, text: &str) -> anyhow::Result<()> {
+        let patch: Table = text.parse()?;
+        let version = patch
+            .get("version")
+            .and_then(Value::as_integer)
+            .unwrap_or(1);
+        if version > PATCH_VERSION {
+            bail!("Patch version {version} is newer than the supported {PATCH_VERSION}")
+        }
+
+        *self.algorithm.lock().unwrap() =
+            read_choice(Some(&patch), "algorithm").unwrap_or(Algorithm::Stack);
+        read_params(Some(&patch), &[("tempo", &self.tempo)]);
+
+        let voice = section(&patch, "voice");
+        read_params(voice, &voice_fields(&self.voice_settings));
+        *self.voice_settings.mode.lock().unwrap() =
+            read_choice(voice, "mode").unwrap_or(VoiceMode::OpenPoly);
+        *self.voice_settings.priority.lock().unwrap() =
+            read_choice(voice, "priority").unwrap_or(NotePriority::Last);
+
+        for (index, op) in self.ops.iter().enumerate() {
+            let table = entry(&patch, "ops", index);
+            read_params(table, &op_fields(op));
+            read_params(
+                table.and_then(|table| section(table, "adsr")),
+                &adsr_fields(&op.adsr_params),
+            );
+            read_params(
+                table.and_then(|table| section(table, "dx")),
+                &dx_fields(&op.dx_params),
+            );
+        }
+
+        for (index, lfo) in self.lfos.iter().enumerate() {
+            read_params(entry(&patch, "lfos", index), &lfo_fields(lfo));
+        }
+
+        let dests = create_modulation_list(self);
+        for (index, slot) in self.mod_slots.iter().enumerate() {
+            let table = entry(&patch, "mod_slots", index);
+            let key = table.and_then(|table| table.get("destination")?.as_str());
+            let destination = dests
+                .iter()
+                .find(|(_, dest)| Some(dest.key.as_str()) == key)
+                .map(|(index, _)| *index);
+            // a slot whose destination is gone must not modulate whatever comes first
+            *slot.source.lock().unwrap() = match destination {
+                Some(_) => read_choice(table, "source").unwrap_or(ModSource::Off),
+                None => ModSource::Off,
+            };
+            *slot.destination.lock().unwrap() = destination.unwrap_or(0);
+            read_params(table, &[("amount", &slot.amount)]);
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_patch())?;
+        Ok(())
+    }
+
+    pub fn load(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.load_patch(&std::fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let params = SynthParams::default();
+        *params.algorithm.lock().unwrap() = Algorithm::Pairs;
+        *params.voice_settings.mode.lock().unwrap() = VoiceMode::Legato;
+        params.voice_settings.glide.set_value(0.3);
+        params.ops[1].ratio.set_value(3.0);
+        params.ops[2].adsr_params.r.set_value(1.5);
+        params.ops[3].dx_params.l2.set_value(25.0);
+        params.lfos[1].rate.set_value(6.5);
+        *params.mod_slots[2].source.lock().unwrap() = ModSource::Lfo2;
+        *params.mod_slots[2].destination.lock().unwrap() = 20;
+        params.mod_slots[2].amount.set_value(-0.5);
+
+        let loaded = SynthParams::default();
+        loaded.load_patch(&params.to_patch()).unwrap();
+        assert_eq!(loaded.algorithm(), Algorithm::Pairs);
+        assert_eq!(
+            *loaded.voice_settings.mode.lock().unwrap(),
+            VoiceMode::Legato
+        );
+        assert_eq!(loaded.voice_settings.glide.value(), 0.3);
+        assert_eq!(loaded.ops[1].ratio.value(), 3.0);
+        assert_eq!(loaded.ops[2].adsr_params.r.value(), 1.5);
+        assert_eq!(loaded.ops[3].dx_params.l2.value(), 25.0);
+        assert_eq!(loaded.lfos[1].rate.value(), 6.5);
+        assert_eq!(loaded.mod_slots[2].source(), ModSource::Lfo2);
+        assert_eq!(loaded.mod_slots[2].destination(), 20);
+        assert_eq!(loaded.mod_slots[2].amount.value(), -0.5);
+        assert_eq!(loaded.to_patch(), params.to_patch());
+    }
+
+    #[test]
+    fn unknown_fields_are_ignored_and_missing_ones_default() {
+        let params = SynthParams::default();
+        params.ops[0].feedback.set_value(0.7);
+        params.ops[0].dx_params.r1.set_value(20.0);
+        *params.mod_slots[0].source.lock().unwrap() = ModSource::Velocity;
+        *params.mod_slots[1].destination.lock().unwrap() = 5;
+        let patch = r#"
+            version = 1
+            algorithm = "Additive"
+            future_setting = true
+
+            [[ops]]
+            volume = 0.5
+            ratio = 2
+            sparkle = 0.3
+
+            [ops.adsr]
+            attack = 40.0
+
+            [ops.dx]
+            l1 = 120
+
+            [[mod_slots]]
+            source = "SomethingNew"
+            destination = "op1.fine"
+            amount = 0.25
+
+            [[mod_slots]]
+            source = "Velocity"
+            destination = "op1.sparkle"
+        "#;
+        params.load_patch(patch).unwrap();
+        assert_eq!(params.algorithm(), Algorithm::Additive);
+        assert_eq!(params.ops[0].volume.value(), 0.5);
+        assert_eq!(params.ops[0].ratio.value(), 2.0);
+        // out of range values are clamped like any other edit
+        assert_eq!(params.ops[0].adsr_params.a.value(), 20.0);
+        assert_eq!(params.ops[0].feedback.value(), 0.0);
+        assert_eq!(params.ops[0].dx_params.r1.value(), 95.0);
+        assert_eq!(params.ops[0].dx_params.l1.value(), 99.0);
+        assert_eq!(params.ops[1].volume.value(), 0.05);
+        assert_eq!(params.mod_slots[0].source(), ModSource::Off);
+        assert_eq!(params.mod_slots[0].destination(), 1);
+        assert_eq!(params.mod_slots[0].amount.value(), 0.25);
+        assert_eq!(params.mod_slots[1].source(), ModSource::Off);
+        assert_eq!(params.mod_slots[1].destination(), 0);
+        assert_eq!(params.tempo.value(), 120.0);
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let params = SynthParams::default();
+        assert!(params.load_patch("version = 2").is_err());
+        assert!(params.load_patch("version = ").is_err());
+        assert!(params.load_patch("").is_ok());
+    }
+}
@@ -1,64 +1,911 @@
-use crate::synth_params::VoiceParams;
+use crate::mpe::MpeEvent;
+use crate::synth::cents_factor;
+use crate::synth_params::{VoiceParams, VoiceSettings};
 use fundsp::prelude::midi_hz;
+use strum_macros::EnumIter;
 
 pub type VoiceIndex = u8;
 
+/// Upper bound of the voice count, every `Param` keeps per voice modulation for this many voices.
+pub const MAX_VOICES: usize = 16;
+
 #[derive(Clone)]
 pub struct Voice {
     pub note: u8,
     pub voice_index: VoiceIndex,
+    /// Key is held and the envelopes are open.
+    pub gate: bool,
+    /// Allocation order of the last note on, used to find the oldest voice.
+    pub started: u64,
+    /// Allocation order of the last note off.
+    pub released: u64,
+    /// Key is up but a pedal keeps the gate open.
+    pub sustained: bool,
+    /// Captured by the sostenuto pedal while the key was down.
+    pub sostenuto: bool,
+    /// MPE member channel the note was played on.
+    pub channel: Option<u8>,
+}
+
+/// Last expression received on an MPE member channel, applied to notes started on it.
+#[derive(Debug, Copy, Clone)]
+struct ChannelExpression {
+    bend: f32,
+    pressure: f32,
+    slide: f32,
+}
+
+impl Default for ChannelExpression {
+    fn default() -> Self {
+        Self {
+            bend: 1.0,
+            pressure: 0.0,
+            slide: 0.0,
+        }
+    }
 }
+
+/// Voice allocation strategy. Every polyphonic mode prefers voices that are not held,
+/// they only differ in which voice is chosen.
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
 pub enum VoiceMode {
+    /// Rotate through the voices, stealing the next one in turn when all are held.
     OpenPoly,
+    /// Take the voice released longest ago, steal the oldest held note.
+    OldestSteal,
+    /// Take or steal the voice with the lowest output level.
+    QuietestSteal,
+    /// Reuse the voice already playing the same note, otherwise like [`VoiceMode::OldestSteal`].
+    SameNoteRetrigger,
+    /// Single voice, every note change retriggers the envelopes.
+    Mono,
+    /// Single voice, overlapping notes only change the pitch.
+    Legato,
+}
+
+impl VoiceMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VoiceMode::OpenPoly => "Poly",
+            VoiceMode::OldestSteal => "Oldest",
+            VoiceMode::QuietestSteal => "Quietest",
+            VoiceMode::SameNoteRetrigger => "Same Note",
+            VoiceMode::Mono => "Mono",
+            VoiceMode::Legato => "Legato",
+        }
+    }
+
+    pub fn is_mono(&self) -> bool {
+        matches!(self, VoiceMode::Mono | VoiceMode::Legato)
+    }
+}
+
+/// Which of the held keys sounds in [`VoiceMode::Mono`] and [`VoiceMode::Legato`].
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
+pub enum NotePriority {
+    Last,
+    Low,
+    High,
+}
+
+impl NotePriority {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotePriority::Last => "Last",
+            NotePriority::Low => "Low",
+            NotePriority::High => "High",
+        }
+    }
 }
+
 pub struct MonoPoly {
     pub voice_size: VoiceIndex,
     pub voice_mode: VoiceMode,
     pub voices: Vec<Voice>,
     pub last_voice_index: VoiceIndex,
+    pub note_priority: NotePriority,
+    /// Portamento time in seconds, only used by the mono modes
+    pub glide_time: f32,
+    /// Voices started by every note
+    pub unison: u8,
+    /// Detune in cents between the outermost unison voices and the note
+    pub unison_detune: f32,
+    /// Pan of the outermost unison voices, 0 keeps every voice centered
+    pub unison_spread: f32,
+    /// Held keys with their velocities in the order they were pressed, only used by the mono modes
+    held_notes: Vec<(u8, u8)>,
+    /// Sustain pedal (CC64) is down
+    pub sustain: bool,
+    /// Sostenuto pedal (CC66) is down
+    pub sostenuto: bool,
+    expressions: [ChannelExpression; 16],
+    order: u64,
 }
 
 impl MonoPoly {
     pub fn new(voice_size: u8) -> Self {
+        assert!(voice_size as usize <= MAX_VOICES);
         Self {
             voice_size,
             voice_mode: VoiceMode::OpenPoly,
-            voices: vec![
-                Voice {
-                    note: 64,
-                    voice_index: 1
-                };
-                voice_size as usize
-            ],
+            voices: (0..voice_size)
+                .map(|voice_index| Voice {
+                    note: 0,
+                    voice_index,
+                    gate: false,
+                    started: 0,
+                    released: 0,
+                    sustained: false,
+                    sostenuto: false,
+                    channel: None,
+                })
+                .collect(),
             last_voice_index: 0,
+            note_priority: NotePriority::Last,
+            glide_time: 0.0,
+            unison: 1,
+            unison_detune: 0.0,
+            unison_spread: 0.0,
+            held_notes: Vec::new(),
+            sustain: false,
+            sostenuto: false,
+            expressions: [ChannelExpression::default(); 16],
+            order: 0,
         }
     }
 
-    pub fn on_voice_on(&mut self, note: u8, velocity: u8, voice_params: &Vec<VoiceParams>) {
-        let curr_index = self.last_voice_index as usize;
+    fn next_order(&mut self) -> u64 {
+        self.order += 1;
+        self.order
+    }
+
+    fn quietest<'a>(
+        voices: impl Iterator<Item = &'a Voice>,
+        voice_params: &Vec<VoiceParams>,
+    ) -> Option<VoiceIndex> {
+        voices
+            .min_by(|a, b| {
+                let a = voice_params[a.voice_index as usize].level.value();
+                let b = voice_params[b.voice_index as usize].level.value();
+                a.total_cmp(&b)
+            })
+            .map(|voice| voice.voice_index)
+    }
+
+    fn unison_size(&self) -> u8 {
+        self.unison.clamp(1, self.voice_size)
+    }
+
+    /// Position of a unison voice in -1..1, used for both detune and pan.
+    fn unison_position(&self, unison_index: u8) -> f32 {
+        let size = self.unison_size();
+        if size < 2 {
+            0.0
+        } else {
+            unison_index as f32 / (size - 1) as f32 * 2.0 - 1.0
+        }
+    }
+
+    /// Picks a voice for `note`, never one of the `taken` voices already started for it.
+    fn allocate(
+        &self,
+        note: u8,
+        taken: &[VoiceIndex],
+        voice_params: &Vec<VoiceParams>,
+    ) -> VoiceIndex {
+        let available = || {
+            self.voices
+                .iter()
+                .filter(|voice| !taken.contains(&voice.voice_index))
+        };
+        if self.voice_mode == VoiceMode::SameNoteRetrigger {
+            let same_note = available()
+                .filter(|voice| voice.note == note && voice.started > 0)
+                .max_by_key(|voice| voice.started);
+            if let Some(voice) = same_note {
+                return voice.voice_index;
+            }
+        }
+
+        let free = || available().filter(|voice| !voice.gate);
+        // voices only held by a pedal are stolen before the ones whose key is down
+        let sustained = || available().filter(|voice| voice.sustained);
+        let held = || available().filter(|voice| voice.gate);
+        let next_in_turn = |candidate: fn(&Voice) -> bool| {
+            (0..self.voice_size)
+                .map(|i| (self.last_voice_index + i) % self.voice_size)
+                .find(|i| !taken.contains(i) && candidate(&self.voices[*i as usize]))
+        };
+        let free_voice = match self.voice_mode {
+            VoiceMode::OpenPoly | VoiceMode::Mono | VoiceMode::Legato => {
+                next_in_turn(|voice| !voice.gate)
+            }
+            VoiceMode::OldestSteal | VoiceMode::SameNoteRetrigger => free()
+                .min_by_key(|voice| voice.released)
+                .map(|voice| voice.voice_index),
+            VoiceMode::QuietestSteal => Self::quietest(free(), voice_params),
+        };
+        let sustained_voice = || match self.voice_mode {
+            VoiceMode::OpenPoly | VoiceMode::Mono | VoiceMode::Legato => {
+                next_in_turn(|voice| voice.sustained)
+            }
+            VoiceMode::OldestSteal | VoiceMode::SameNoteRetrigger => sustained()
+                .min_by_key(|voice| voice.started)
+                .map(|voice| voice.voice_index),
+            VoiceMode::QuietestSteal => Self::quietest(sustained(), voice_params),
+        };
+        free_voice
+            .or_else(sustained_voice)
+            .unwrap_or_else(|| match self.voice_mode {
+                VoiceMode::OpenPoly | VoiceMode::Mono | VoiceMode::Legato => self.last_voice_index,
+                VoiceMode::OldestSteal | VoiceMode::SameNoteRetrigger => held()
+                    .min_by_key(|voice| voice.started)
+                    .map(|voice| voice.voice_index)
+                    .unwrap_or(0),
+                VoiceMode::QuietestSteal => Self::quietest(held(), voice_params).unwrap_or(0),
+            })
+    }
+
+    pub fn apply_settings(&mut self, settings: &VoiceSettings, voice_params: &Vec<VoiceParams>) {
+        let voice_mode = *settings.mode.lock().unwrap();
+        let unison = settings.unison.value().round() as u8;
+        if voice_mode != self.voice_mode || unison != self.unison {
+            // notes started in the previous mode would never get their note off
+            for voice_index in 0..self.voice_size {
+                self.release(voice_index, voice_params);
+            }
+            self.held_notes.clear();
+            self.voice_mode = voice_mode;
+            self.unison = unison;
+        }
+        self.note_priority = *settings.priority.lock().unwrap();
+        self.glide_time = settings.glide.value();
+        self.unison_detune = settings.unison_detune.value();
+        self.unison_spread = settings.unison_spread.value();
+    }
+
+    fn play(
+        &mut self,
+        voice_index: VoiceIndex,
+        note: u8,
+        velocity: u8,
+        glide: f32,
+        unison_position: f32,
+        voice_params: &Vec<VoiceParams>,
+    ) {
+        let curr_index = voice_index as usize;
         let voice_params = &voice_params[curr_index];
-        println!("{note} {velocity} {curr_index} {}", self.voices.len());
+        let started = self.next_order();
         self.voices[curr_index] = Voice {
             note,
-            voice_index: self.last_voice_index,
+            voice_index,
+            gate: true,
+            started,
+            released: 0,
+            sustained: false,
+            sostenuto: false,
+            channel: None,
         };
 
+        voice_params.glide.set_value(glide);
+        voice_params
+            .detune
+            .set_value(cents_factor(unison_position * self.unison_detune));
+        voice_params
+            .pan
+            .set_value(unison_position * self.unison_spread);
         voice_params.pitch.set_value(midi_hz(note as f32));
-        voice_params.volume.set_value(velocity as f32 / 127.0);
-        voice_params.pitch_bend.set_value(1.0);
+        voice_params.note.set_value(note as f32);
+        voice_params.velocity.set_value(velocity as f32 / 127.0);
+        voice_params.aftertouch.set_value(0.0);
+        voice_params.channel_bend.set_value(1.0);
         voice_params.control.set_value(1.0);
+        voice_params
+            .trigger
+            .set_value(voice_params.trigger.value() + 1.0);
+    }
+
+    fn release(&mut self, voice_index: VoiceIndex, voice_params: &Vec<VoiceParams>) {
+        if self.voices[voice_index as usize].gate {
+            let released = self.next_order();
+            let voice = &mut self.voices[voice_index as usize];
+            voice.gate = false;
+            voice.released = released;
+            voice.sustained = false;
+            voice.sostenuto = false;
+            voice_params[voice_index as usize].control.set_value(-1.0);
+        }
+    }
 
-        self.last_voice_index = (self.last_voice_index + 1) % self.voice_size;
+    /// Releases a voice whose key went up, unless a pedal holds it.
+    fn key_up(&mut self, voice_index: VoiceIndex, voice_params: &Vec<VoiceParams>) {
+        let voice = &mut self.voices[voice_index as usize];
+        if voice.gate && (self.sustain || voice.sostenuto) {
+            voice.sustained = true;
+        } else {
+            self.release(voice_index, voice_params);
+        }
     }
 
-    pub fn on_voice_off(&mut self, note: u8, voice_params: &Vec<VoiceParams>) {
+    pub fn on_poly_pressure(&self, note: u8, pressure: u8, voice_params: &Vec<VoiceParams>) {
         self.voices
             .iter()
-            .enumerate()
-            .filter(|(_, voice)| voice.note == note)
-            .for_each(|(i, _)| {
-                let voice_params = &voice_params[i];
-                voice_params.control.set_value(-1.0);
+            .filter(|voice| voice.note == note && voice.gate)
+            .for_each(|voice| {
+                voice_params[voice.voice_index as usize]
+                    .aftertouch
+                    .set_value(pressure as f32 / 127.0)
             });
     }
+
+    /// MPE notes are played like any other note, following the voice mode and unison,
+    /// and every member channel bends, presses and slides only the voices playing on it.
+    /// In the mono modes the voices follow the channel of the note they last started.
+    pub fn on_mpe(&mut self, event: MpeEvent, voice_params: &Vec<VoiceParams>) {
+        match event {
+            MpeEvent::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => {
+                let expression = self.expressions[channel as usize];
+                for voice_index in self.note_on(note, velocity, voice_params) {
+                    self.voices[voice_index as usize].channel = Some(channel);
+                    let voice_params = &voice_params[voice_index as usize];
+                    voice_params.channel_bend.set_value(expression.bend);
+                    voice_params.pressure.set_value(expression.pressure);
+                    voice_params.slide.set_value(expression.slide);
+                }
+            }
+            MpeEvent::NoteOff { note, .. } if self.voice_mode.is_mono() => {
+                self.on_voice_off(note, voice_params)
+            }
+            MpeEvent::NoteOff { channel, note } => {
+                for voice_index in 0..self.voice_size {
+                    let voice = &self.voices[voice_index as usize];
+                    if voice.note == note && voice.channel == Some(channel) {
+                        self.key_up(voice_index, voice_params);
+                    }
+                }
+            }
+            MpeEvent::Bend { channel, semitones } => {
+                let bend = 2.0_f32.powf(semitones / 12.0);
+                self.expressions[channel as usize].bend = bend;
+                self.channel_voices(channel, voice_params)
+                    .for_each(|voice_params| voice_params.channel_bend.set_value(bend));
+            }
+            MpeEvent::Pressure { channel, pressure } => {
+                let pressure = pressure as f32 / 127.0;
+                self.expressions[channel as usize].pressure = pressure;
+                self.channel_voices(channel, voice_params)
+                    .for_each(|voice_params| voice_params.pressure.set_value(pressure));
+            }
+            MpeEvent::Slide { channel, slide } => {
+                let slide = slide as f32 / 127.0;
+                self.expressions[channel as usize].slide = slide;
+                self.channel_voices(channel, voice_params)
+                    .for_each(|voice_params| voice_params.slide.set_value(slide));
+            }
+        }
+    }
+
+    fn channel_voices<'a>(
+        &'a self,
+        channel: u8,
+        voice_params: &'a Vec<VoiceParams>,
+    ) -> impl Iterator<Item = &'a VoiceParams> {
+        self.voices
+            .iter()
+            .filter(move |voice| voice.gate && voice.channel == Some(channel))
+            .map(|voice| &voice_params[voice.voice_index as usize])
+    }
+
+    pub fn on_sustain(&mut self, down: bool, voice_params: &Vec<VoiceParams>) {
+        self.sustain = down;
+        if !down {
+            for voice_index in 0..self.voice_size {
+                let voice = &self.voices[voice_index as usize];
+                if voice.sustained && !voice.sostenuto {
+                    self.release(voice_index, voice_params);
+                }
+            }
+        }
+    }
+
+    /// Pressing the pedal captures the voices whose keys are down at that moment,
+    /// only those outlive their note off.
+    pub fn on_sostenuto(&mut self, down: bool, voice_params: &Vec<VoiceParams>) {
+        self.sostenuto = down;
+        for voice_index in 0..self.voice_size {
+            let voice = &mut self.voices[voice_index as usize];
+            if down {
+                voice.sostenuto = voice.gate && !voice.sustained;
+            } else if voice.sostenuto {
+                voice.sostenuto = false;
+                if voice.sustained && !self.sustain {
+                    self.release(voice_index, voice_params);
+                }
+            }
+        }
+    }
+
+    fn priority_note(&self) -> Option<(u8, u8)> {
+        match self.note_priority {
+            NotePriority::Last => self.held_notes.last(),
+            NotePriority::Low => self.held_notes.iter().min_by_key(|(note, _)| *note),
+            NotePriority::High => self.held_notes.iter().max_by_key(|(note, _)| *note),
+        }
+        .copied()
+    }
+
+    /// Plays the held key chosen by the note priority on the first voices,
+    /// one per unison voice, gliding when a key was already sounding.
+    fn update_mono(&mut self, voice_params: &Vec<VoiceParams>) {
+        let unison = self.unison_size();
+        let Some((note, velocity)) = self.priority_note() else {
+            for voice_index in 0..unison {
+                self.key_up(voice_index, voice_params);
+            }
+            return;
+        };
+        // a key is down again, the pedal no longer holds the voices
+        for voice in self.voices.iter_mut().take(unison as usize) {
+            voice.sustained = false;
+        }
+        let voice = &self.voices[0];
+        if voice.gate && voice.note == note {
+            return;
+        }
+        let sounding = voice.gate;
+        let glide = if sounding { self.glide_time } else { 0.0 };
+        for voice_index in 0..unison {
+            let curr_index = voice_index as usize;
+            if sounding && self.voice_mode == VoiceMode::Legato {
+                self.voices[curr_index].note = note;
+                voice_params[curr_index].glide.set_value(glide);
+                voice_params[curr_index]
+                    .pitch
+                    .set_value(midi_hz(note as f32));
+                voice_params[curr_index].note.set_value(note as f32);
+            } else {
+                let position = self.unison_position(voice_index);
+                self.play(voice_index, note, velocity, glide, position, voice_params);
+            }
+        }
+    }
+
+    pub fn on_voice_on(&mut self, note: u8, velocity: u8, voice_params: &Vec<VoiceParams>) {
+        self.note_on(note, velocity, voice_params);
+    }
+
+    /// Starts `note` and returns the voices now playing it, none when a mono mode keeps
+    /// playing a key of higher priority.
+    fn note_on(
+        &mut self,
+        note: u8,
+        velocity: u8,
+        voice_params: &Vec<VoiceParams>,
+    ) -> Vec<VoiceIndex> {
+        if self.voice_mode.is_mono() {
+            self.held_notes.retain(|(held, _)| *held != note);
+            self.held_notes.push((note, velocity));
+            self.update_mono(voice_params);
+            return (0..self.unison_size())
+                .filter(|i| {
+                    let voice = &self.voices[*i as usize];
+                    voice.gate && voice.note == note
+                })
+                .collect();
+        }
+
+        let mut taken = Vec::new();
+        for unison_index in 0..self.unison_size() {
+            let voice_index = self.allocate(note, &taken, voice_params);
+            println!("{note} {velocity} {voice_index} {}", self.voices.len());
+            let position = self.unison_position(unison_index);
+            self.play(voice_index, note, velocity, 0.0, position, voice_params);
+            self.last_voice_index = (voice_index + 1) % self.voice_size;
+            taken.push(voice_index);
+        }
+        taken
+    }
+
+    pub fn on_voice_off(&mut self, note: u8, voice_params: &Vec<VoiceParams>) {
+        if self.voice_mode.is_mono() {
+            self.held_notes.retain(|(held, _)| *held != note);
+            self.update_mono(voice_params);
+            return;
+        }
+
+        for voice_index in 0..self.voice_size {
+            if self.voices[voice_index as usize].note == note {
+                self.key_up(voice_index, voice_params);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(voice_mode: VoiceMode) -> (MonoPoly, Vec<VoiceParams>) {
+        let mut mono_poly = MonoPoly::new(4);
+        mono_poly.voice_mode = voice_mode;
+        let voice_params = (0..4).map(|_| VoiceParams::default()).collect();
+        (mono_poly, voice_params)
+    }
+
+    fn voice_of(mono_poly: &MonoPoly, note: u8) -> Option<usize> {
+        mono_poly
+            .voices
+            .iter()
+            .position(|voice| voice.note == note && voice.gate)
+    }
+
+    #[test]
+    fn open_poly_rotates_through_voices() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OpenPoly);
+        for note in 60..64 {
+            mono_poly.on_voice_on(note, 100, &voice_params);
+        }
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+        assert_eq!(voice_of(&mono_poly, 63), Some(3));
+    }
+
+    #[test]
+    fn open_poly_skips_held_voices() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OpenPoly);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_on(61, 100, &voice_params);
+        mono_poly.on_voice_on(62, 100, &voice_params);
+        mono_poly.on_voice_off(61, &voice_params);
+        mono_poly.on_voice_on(63, 100, &voice_params);
+        mono_poly.on_voice_on(64, 100, &voice_params);
+
+        // the held note on voice 0 survives the wrap around
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+        assert_eq!(voice_of(&mono_poly, 63), Some(3));
+        assert_eq!(voice_of(&mono_poly, 64), Some(1));
+    }
+
+    #[test]
+    fn open_poly_steals_next_voice_when_all_held() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OpenPoly);
+        for note in 60..65 {
+            mono_poly.on_voice_on(note, 100, &voice_params);
+        }
+        assert_eq!(voice_of(&mono_poly, 60), None);
+        assert_eq!(voice_of(&mono_poly, 64), Some(0));
+    }
+
+    #[test]
+    fn oldest_steal_prefers_longest_released_voice() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OldestSteal);
+        for note in 60..64 {
+            mono_poly.on_voice_on(note, 100, &voice_params);
+        }
+        mono_poly.on_voice_off(62, &voice_params);
+        mono_poly.on_voice_off(61, &voice_params);
+        mono_poly.on_voice_on(70, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 70), Some(2));
+        mono_poly.on_voice_on(71, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 71), Some(1));
+    }
+
+    #[test]
+    fn oldest_steal_takes_oldest_held_note() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OldestSteal);
+        for note in [60, 61, 62, 63] {
+            mono_poly.on_voice_on(note, 100, &voice_params);
+        }
+        mono_poly.on_voice_off(60, &voice_params);
+        mono_poly.on_voice_on(64, 100, &voice_params);
+        mono_poly.on_voice_on(65, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 64), Some(0));
+        assert_eq!(voice_of(&mono_poly, 65), Some(1));
+        assert_eq!(voice_of(&mono_poly, 61), None);
+    }
+
+    #[test]
+    fn quietest_steal_takes_lowest_level() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::QuietestSteal);
+        for note in 60..64 {
+            mono_poly.on_voice_on(note, 100, &voice_params);
+        }
+        for (i, level) in [0.8, 0.3, 0.1, 0.5].iter().enumerate() {
+            voice_params[i].level.set_value(*level);
+        }
+        mono_poly.on_voice_on(64, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 64), Some(2));
+
+        // a released voice wins even when a held one is quieter
+        voice_params[2].level.set_value(0.0);
+        mono_poly.on_voice_off(60, &voice_params);
+        mono_poly.on_voice_on(65, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 65), Some(0));
+    }
+
+    #[test]
+    fn same_note_retrigger_reuses_voice() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::SameNoteRetrigger);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_on(62, 100, &voice_params);
+        mono_poly.on_voice_off(60, &voice_params);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+        assert_eq!(
+            mono_poly.voices.iter().filter(|voice| voice.gate).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn mono_returns_to_previous_held_note() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::Mono);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_on(64, 100, &voice_params);
+        mono_poly.on_voice_on(67, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 67), Some(0));
+
+        mono_poly.on_voice_off(64, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 67), Some(0));
+        mono_poly.on_voice_off(67, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+        assert_eq!(voice_params[0].note.value(), 60.0);
+
+        mono_poly.on_voice_off(60, &voice_params);
+        assert!(!mono_poly.voices[0].gate);
+        assert_eq!(voice_params[0].control.value(), -1.0);
+    }
+
+    #[test]
+    fn mono_retriggers_and_legato_does_not() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::Mono);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_on(62, 100, &voice_params);
+        assert_eq!(voice_params[0].trigger.value(), 2.0);
+
+        let (mut mono_poly, voice_params) = setup(VoiceMode::Legato);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_on(62, 100, &voice_params);
+        mono_poly.on_voice_off(62, &voice_params);
+        assert_eq!(voice_params[0].trigger.value(), 1.0);
+        assert_eq!(voice_params[0].note.value(), 60.0);
+    }
+
+    #[test]
+    fn note_priority_picks_held_note() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::Mono);
+        mono_poly.note_priority = NotePriority::Low;
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_on(64, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+        mono_poly.on_voice_on(55, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 55), Some(0));
+
+        let (mut mono_poly, voice_params) = setup(VoiceMode::Mono);
+        mono_poly.note_priority = NotePriority::High;
+        mono_poly.on_voice_on(64, 100, &voice_params);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 64), Some(0));
+        mono_poly.on_voice_off(64, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+    }
+
+    #[test]
+    fn glide_only_between_overlapping_notes() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::Legato);
+        mono_poly.glide_time = 0.2;
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        assert_eq!(voice_params[0].glide.value(), 0.0);
+        mono_poly.on_voice_on(62, 100, &voice_params);
+        assert_eq!(voice_params[0].glide.value(), 0.2);
+        mono_poly.on_voice_off(62, &voice_params);
+        mono_poly.on_voice_off(60, &voice_params);
+        mono_poly.on_voice_on(64, 100, &voice_params);
+        assert_eq!(voice_params[0].glide.value(), 0.0);
+    }
+
+    #[test]
+    fn unison_stacks_detuned_and_panned_voices() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OldestSteal);
+        mono_poly.unison = 3;
+        mono_poly.unison_detune = 12.0;
+        mono_poly.unison_spread = 1.0;
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        let voices: Vec<usize> = (0..4)
+            .filter(|i| mono_poly.voices[*i].gate && mono_poly.voices[*i].note == 60)
+            .collect();
+        assert_eq!(voices.len(), 3);
+        let pans: Vec<f32> = voices
+            .iter()
+            .map(|i| voice_params[*i].pan.value())
+            .collect();
+        assert_eq!(pans, vec![-1.0, 0.0, 1.0]);
+        assert!(voice_params[voices[0]].detune.value() < 1.0);
+        assert_eq!(voice_params[voices[1]].detune.value(), 1.0);
+        assert!(voice_params[voices[2]].detune.value() > 1.0);
+
+        mono_poly.on_voice_on(62, 100, &voice_params);
+        assert_eq!(
+            mono_poly
+                .voices
+                .iter()
+                .filter(|voice| voice.note == 62)
+                .count(),
+            3
+        );
+        mono_poly.on_voice_off(60, &voice_params);
+        mono_poly.on_voice_off(62, &voice_params);
+        assert!(mono_poly.voices.iter().all(|voice| !voice.gate));
+    }
+
+    #[test]
+    fn mono_unison_moves_every_voice() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::Legato);
+        mono_poly.unison = 2;
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_on(64, 100, &voice_params);
+        assert_eq!(voice_params[0].note.value(), 64.0);
+        assert_eq!(voice_params[1].note.value(), 64.0);
+        assert!(!mono_poly.voices[2].gate);
+    }
+
+    #[test]
+    fn sustain_defers_note_off() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OldestSteal);
+        mono_poly.on_sustain(true, &voice_params);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_off(60, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+        assert_eq!(voice_params[0].control.value(), 1.0);
+
+        mono_poly.on_sustain(false, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), None);
+        assert_eq!(voice_params[0].control.value(), -1.0);
+    }
+
+    #[test]
+    fn sustain_release_keeps_keys_that_are_down() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OldestSteal);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_sustain(true, &voice_params);
+        mono_poly.on_voice_on(62, 100, &voice_params);
+        mono_poly.on_voice_off(62, &voice_params);
+        mono_poly.on_sustain(false, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+        assert_eq!(voice_of(&mono_poly, 62), None);
+    }
+
+    #[test]
+    fn sostenuto_holds_only_captured_notes() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OldestSteal);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_sostenuto(true, &voice_params);
+        mono_poly.on_voice_off(60, &voice_params);
+        mono_poly.on_voice_on(62, 100, &voice_params);
+        mono_poly.on_voice_off(62, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+        assert_eq!(voice_of(&mono_poly, 62), None);
+
+        mono_poly.on_sostenuto(false, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), None);
+    }
+
+    #[test]
+    fn sustained_voices_are_stolen_before_held_keys() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OldestSteal);
+        for note in 60..64 {
+            mono_poly.on_voice_on(note, 100, &voice_params);
+        }
+        mono_poly.on_sustain(true, &voice_params);
+        mono_poly.on_voice_off(62, &voice_params);
+        mono_poly.on_voice_on(64, 100, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 64), Some(2));
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+    }
+
+    #[test]
+    fn mono_sustain_holds_last_note() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::Mono);
+        mono_poly.on_sustain(true, &voice_params);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_off(60, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+        mono_poly.on_sustain(false, &voice_params);
+        assert_eq!(voice_of(&mono_poly, 60), None);
+    }
+
+    #[test]
+    fn poly_pressure_reaches_only_its_voices() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OldestSteal);
+        mono_poly.on_voice_on(60, 100, &voice_params);
+        mono_poly.on_voice_on(62, 100, &voice_params);
+        mono_poly.on_poly_pressure(62, 127, &voice_params);
+        assert_eq!(voice_params[0].aftertouch.value(), 0.0);
+        assert_eq!(voice_params[1].aftertouch.value(), 1.0);
+    }
+
+    #[test]
+    fn mpe_expression_follows_channel() {
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OldestSteal);
+        mono_poly.on_mpe(
+            MpeEvent::Bend {
+                channel: 2,
+                semitones: 12.0,
+            },
+            &voice_params,
+        );
+        let note_on = |channel, note| MpeEvent::NoteOn {
+            channel,
+            note,
+            velocity: 100,
+        };
+        mono_poly.on_mpe(note_on(1, 60), &voice_params);
+        mono_poly.on_mpe(note_on(2, 60), &voice_params);
+        assert_eq!(voice_params[0].channel_bend.value(), 1.0);
+        assert_eq!(voice_params[1].channel_bend.value(), 2.0);
+
+        mono_poly.on_mpe(
+            MpeEvent::Pressure {
+                channel: 1,
+                pressure: 127,
+            },
+            &voice_params,
+        );
+        assert_eq!(voice_params[0].pressure.value(), 1.0);
+        assert_eq!(voice_params[1].pressure.value(), 0.0);
+
+        mono_poly.on_mpe(
+            MpeEvent::NoteOff {
+                channel: 2,
+                note: 60,
+            },
+            &voice_params,
+        );
+        assert!(mono_poly.voices[0].gate);
+        assert!(!mono_poly.voices[1].gate);
+    }
+
+    #[test]
+    fn mpe_notes_follow_mono_and_unison() {
+        let note_on = |channel, note| MpeEvent::NoteOn {
+            channel,
+            note,
+            velocity: 100,
+        };
+        let (mut mono_poly, voice_params) = setup(VoiceMode::Mono);
+        mono_poly.on_mpe(note_on(1, 60), &voice_params);
+        mono_poly.on_mpe(note_on(2, 64), &voice_params);
+        assert_eq!(voice_of(&mono_poly, 64), Some(0));
+        assert_eq!(mono_poly.voices[0].channel, Some(2));
+        assert!(!mono_poly.voices[1].gate);
+
+        mono_poly.on_mpe(
+            MpeEvent::NoteOff {
+                channel: 2,
+                note: 64,
+            },
+            &voice_params,
+        );
+        assert_eq!(voice_of(&mono_poly, 60), Some(0));
+
+        let (mut mono_poly, voice_params) = setup(VoiceMode::OpenPoly);
+        mono_poly.unison = 2;
+        mono_poly.on_mpe(
+            MpeEvent::Bend {
+                channel: 3,
+                semitones: 12.0,
+            },
+            &voice_params,
+        );
+        mono_poly.on_mpe(note_on(3, 60), &voice_params);
+        for voice_index in 0..2 {
+            assert!(mono_poly.voices[voice_index].gate);
+            assert_eq!(mono_poly.voices[voice_index].channel, Some(3));
+            assert_eq!(voice_params[voice_index].channel_bend.value(), 2.0);
+        }
+        assert!(!mono_poly.voices[2].gate);
+    }
 }
@@ -3,56 +3,337 @@ use cpal::{BufferSize, Device, FromSample, SampleFormat, SampleRate, SizedSample
 use fundsp::audiounit::AudioUnit;
 use fundsp::combinator::An;
 use fundsp::prelude::{
-    constant, oversample, pass, sine_hz, var, AudioNode, NetBackend, Shared, U0, U1,
+    constant, feedback2, map, monitor, oversample, panner, pass, sink, var, AudioNode, Frame,
+    Meter, Net, NetBackend, Shared, U0, U1, U2, U3, U6,
 };
 
 use crate::adsr::adsr;
-use crate::p_sine::p_sine;
-use crate::param::{param, param_sink, Param};
+use crate::algorithm::Algorithm;
+use crate::glide::glide;
+use crate::key_scaling::{level_scale, rate_scale, velocity_scale, KeyCurve};
+use crate::lfo::{lfo, LFOS};
+use crate::modulation::{ModDestinations, ModRoutes, ModSlot, ModSource};
+use crate::p_wave::p_wave;
+use crate::param::{
+    param, param_release, param_sink, voice_param, Contributions, ModTarget, Param,
+};
 use crate::poly::VoiceIndex;
-use crate::synth_params::{AdsrParams, OpParams, SynthParams, VoiceParams};
+use crate::synth_params::{FreqMode, OpParams, SynthParams, VoiceParams};
 
 pub fn c_adsr(
-    adsr_params: &AdsrParams,
-    control: &Shared,
+    op_params: &OpParams,
+    voice_params: &VoiceParams,
+    voice: usize,
 ) -> An<impl AudioNode<Inputs = U0, Outputs = U1>> {
-    (var(&adsr_params.a)
-        | var(&adsr_params.d)
-        | var(&adsr_params.s)
-        | var(&adsr_params.r)
-        | var(&control))
-        >> adsr()
+    (var(&voice_params.note) | voice_param(&op_params.key_rate_scaling, voice))
+        >> map(|f: &Frame<f32, U2>| rate_scale(f[0], f[1]))
+        >> adsr(
+            &op_params.adsr_params,
+            &op_params.dx_params,
+            &op_params.env_kind,
+            &voice_params.control,
+            &voice_params.trigger,
+            voice,
+        )
 }
 
+/// Operator gain from velocity sensitivity and keyboard level scaling.
+pub fn op_level(
+    voice_params: &VoiceParams,
+    op_params: &OpParams,
+    voice: usize,
+) -> An<impl AudioNode<Inputs = U0, Outputs = U1>> {
+    let velocity = (var(&voice_params.velocity) | voice_param(&op_params.velocity_sens, voice))
+        >> map(|f: &Frame<f32, U2>| velocity_scale(f[0], f[1]));
+    let key = (var(&voice_params.note)
+        | voice_param(&op_params.key_breakpoint, voice)
+        | voice_param(&op_params.key_left_depth, voice)
+        | voice_param(&op_params.key_right_depth, voice)
+        | voice_param(&op_params.key_left_curve, voice)
+        | voice_param(&op_params.key_right_curve, voice))
+        >> map(|f: &Frame<f32, U6>| {
+            level_scale(
+                f[0],
+                f[1],
+                f[2],
+                f[3],
+                KeyCurve::from_value(f[4]),
+                KeyCurve::from_value(f[5]),
+            )
+        });
+    velocity * key * voice_param(&op_params.volume, voice)
+}
+
+/// One operator of a voice, its parameters include the modulation of the voice.
 pub fn op(
     voice_params: &VoiceParams,
     op_params: &OpParams,
+    voice: usize,
+    op_index: usize,
 ) -> An<impl AudioNode<Inputs = U1, Outputs = U1>> {
-    let bf = || var(&voice_params.pitch) * var(&voice_params.pitch_bend) * param(&op_params.ratio);
-    (bf() | pass())
-        >> p_sine::<f32>()
-            * c_adsr(&op_params.adsr_params, &voice_params.control)
-            * param(&op_params.volume)
+    let frequency = (glide(&voice_params.pitch, &voice_params.glide)
+        * var(&voice_params.pitch_bend)
+        * var(&voice_params.channel_bend)
+        * var(&voice_params.detune)
+        | voice_param(&op_params.ratio, voice)
+        | voice_param(&op_params.fine, voice)
+        | voice_param(&op_params.freq_mode, voice)
+        | voice_param(&op_params.fixed_freq, voice)
+        | voice_param(&op_params.detune, voice))
+        >> map(|f: &Frame<f32, U6>| {
+            op_frequency(f[0], f[1], f[2], FreqMode::from_value(f[3]), f[4], f[5])
+        });
+    // the operator output is fed back into its own phase input one sample later
+    feedback2(
+        (frequency | pass() | voice_param(&op_params.waveform, voice))
+            >> p_wave::<f32>()
+                * (c_adsr(op_params, voice_params, voice)
+                    >> monitor(&voice_params.env_levels[op_index], Meter::Sample))
+                * op_level(voice_params, op_params, voice),
+        pass() * voice_param(&op_params.feedback, voice),
+    )
+}
+
+/// LFO of a single voice, only audible through the modulation matrix.
+fn voice_lfo(
+    synth_params: &SynthParams,
+    voice_index: VoiceIndex,
+    index: usize,
+) -> An<impl AudioNode<Inputs = U0, Outputs = U0>> {
+    let voice_params = &synth_params.voice_params[voice_index as usize];
+    lfo(
+        &synth_params.lfos[index],
+        &synth_params.tempo,
+        vec![voice_params.trigger.clone()],
+        (voice_index as usize * LFOS + index) as u64,
+        Some(voice_index as usize),
+    ) >> monitor(&voice_params.lfo_levels[index], Meter::Sample)
+        >> sink()
+}
+
+/// Voice output from the summed carriers, scaled so every algorithm plays at the level
+/// of a single carrier.
+fn voice_output(
+    synth_params: &SynthParams,
+    voice_index: VoiceIndex,
+    ops: An<impl AudioNode<Inputs = U0, Outputs = U1> + 'static>,
+) -> Box<dyn AudioUnit> {
+    let voice_params = &synth_params.voice_params[voice_index as usize];
+    let gain = 1.0 / synth_params.algorithm().carriers().len() as f32;
+    Box::new(
+        (ops * gain >> monitor(&voice_params.level, Meter::Peak(0.99)) | var(&voice_params.pan))
+            >> panner()
+            | voice_lfo(synth_params, voice_index, 0)
+            | voice_lfo(synth_params, voice_index, 1),
+    )
+}
+
+/// LFO shared by all voices, restarted by a note on of any of them.
+pub fn global_lfo(synth_params: &SynthParams, index: usize) -> Box<dyn AudioUnit> {
+    let triggers = synth_params
+        .voice_params
+        .iter()
+        .map(|voice_params| voice_params.trigger.clone())
+        .collect();
+    Box::new(
+        lfo(
+            &synth_params.lfos[index],
+            &synth_params.tempo,
+            triggers,
+            (synth_params.voice_params.len() * LFOS + index) as u64,
+            None,
+        ) >> monitor(&synth_params.lfo_levels[index], Meter::Sample)
+            >> sink(),
+    )
+}
+
+pub fn coarse_ratio(coarse: f32) -> f32 {
+    if coarse < 1.0 {
+        0.5 + coarse * 0.5
+    } else {
+        coarse
+    }
+}
+
+pub fn cents_factor(cents: f32) -> f32 {
+    2.0_f32.powf(cents / 1200.0)
+}
+
+pub fn op_frequency(
+    pitch: f32,
+    coarse: f32,
+    fine: f32,
+    mode: FreqMode,
+    fixed_freq: f32,
+    detune: f32,
+) -> f32 {
+    let frequency = match mode {
+        FreqMode::Ratio => pitch * coarse_ratio(coarse) * (1.0 + fine),
+        FreqMode::Fixed => fixed_freq,
+    };
+    frequency * cents_factor(detune)
 }
 
 pub fn create_sound(synth_params: &SynthParams, voice_index: VoiceIndex) -> Box<dyn AudioUnit> {
     let voice_params = &synth_params.voice_params[voice_index as usize];
+    let o = |i: usize| op(&voice_params, &synth_params.ops[i], voice_index as usize, i);
+    let m = |i: usize| constant(1.) >> o(i);
 
+    match synth_params.algorithm() {
+        Algorithm::Stack => voice_output(synth_params, voice_index, m(3) >> o(2) >> o(1) >> o(0)),
+        Algorithm::BranchStack => {
+            voice_output(synth_params, voice_index, (m(3) + m(2)) >> o(1) >> o(0))
+        }
+        Algorithm::ThreeToOne => {
+            voice_output(synth_params, voice_index, (m(3) + m(2) + m(1)) >> o(0))
+        }
+        Algorithm::Pairs => {
+            voice_output(synth_params, voice_index, (m(3) >> o(2)) + (m(1) >> o(0)))
+        }
+        Algorithm::StackAndCarrier => {
+            voice_output(synth_params, voice_index, (m(3) >> o(2) >> o(1)) + m(0))
+        }
+        Algorithm::OneToThree => voice_output(
+            synth_params,
+            voice_index,
+            m(3) >> (o(2) ^ o(1) ^ o(0)) >> (pass() + pass() + pass()),
+        ),
+        Algorithm::Additive => voice_output(synth_params, voice_index, m(3) + m(2) + m(1) + m(0)),
+    }
+}
+
+/// Output of an LFO as seen by one voice, following the LFO mode.
+fn voice_lfo_level(
+    synth_params: &SynthParams,
+    voice_params: &VoiceParams,
+    index: usize,
+) -> An<impl AudioNode<Inputs = U0, Outputs = U1>> {
+    (var(&synth_params.lfo_levels[index])
+        | var(&voice_params.lfo_levels[index])
+        | param(&synth_params.lfos[index].per_voice))
+        >> map(|f: &Frame<f32, U3>| if f[2] >= 0.5 { f[1] } else { f[0] })
+}
+
+fn slot_output(
+    source: An<impl AudioNode<Inputs = U0, Outputs = U1> + 'static>,
+    mod_slot: &ModSlot,
+    dest: &Param,
+    contributions: &Contributions,
+) -> Box<dyn AudioUnit> {
     Box::new(
-        constant(1.)
-            >> op(&voice_params, &synth_params.ops[3])
-            >> op(&voice_params, &synth_params.ops[2])
-            >> op(&voice_params, &synth_params.ops[1])
-            >> op(&voice_params, &synth_params.ops[0]) * var(&voice_params.volume),
+        source * param(&mod_slot.amount) * dest.range()
+            >> param_sink(dest, ModTarget::Global, contributions),
     )
 }
 
-pub fn sine_lfo(param: &Param) -> Box<dyn AudioUnit> {
-    Box::new(sine_hz::<f32>(0.5) * 10.0 >> param_sink(param))
+/// Modulates every voice on its own, `source` gives the value of one voice.
+/// Readers outside of the voices follow the strongest voice.
+fn voice_slot_output<N: AudioNode<Inputs = U0, Outputs = U1> + 'static>(
+    synth_params: &SynthParams,
+    source: impl Fn(&VoiceParams) -> An<N>,
+    mod_slot: &ModSlot,
+    dest: &Param,
+    contributions: &Contributions,
+) -> Box<dyn AudioUnit> {
+    let mut net = Net::new(0, 0);
+    for (voice, voice_params) in synth_params.voice_params.iter().enumerate() {
+        net.push(Box::new(
+            source(voice_params) * voice_param(&mod_slot.amount, voice) * dest.range()
+                >> param_sink(dest, ModTarget::Voice(voice), contributions),
+        ));
+    }
+    // pushed last so it sees what the voices wrote in this block
+    let voices = contributions.clone();
+    net.push(Box::new(
+        map(move |_: &Frame<f32, U0>| voices.strongest_voice())
+            >> param_sink(dest, ModTarget::Summary, contributions),
+    ));
+    Box::new(net)
+}
+
+/// Modulation matrix slot writing its source scaled by the slot amount into the destination.
+/// Per voice sources write the offset of each voice, see [`Param::voice_value`].
+/// The node first takes back what the slot wrote into its previous destinations.
+/// Slot nodes run after the voices, so voices hear the matrix one block late.
+pub fn mod_slot(
+    slot: usize,
+    synth_params: &SynthParams,
+    dests: &ModDestinations,
+    routes: &mut ModRoutes,
+) -> Box<dyn AudioUnit> {
+    let mod_slot = &synth_params.mod_slots[slot];
+    let dest = &dests[mod_slot.destination()].1.dest;
+    let (contributions, retired) = routes.reroute(slot, dest);
+    let mut net = Net::new(0, 0);
+    net.push(Box::new(param_release(retired)));
+    net.push(slot_source(synth_params, mod_slot, dest, &contributions));
+    Box::new(net)
+}
+
+/// Source of a slot writing into `dest`, keeping what it adds in `contributions`.
+fn slot_source(
+    synth_params: &SynthParams,
+    mod_slot: &ModSlot,
+    dest: &Param,
+    contributions: &Contributions,
+) -> Box<dyn AudioUnit> {
+    let output = |source: fn(&VoiceParams) -> &Shared| {
+        voice_slot_output(
+            synth_params,
+            |v| var(source(v)),
+            mod_slot,
+            dest,
+            contributions,
+        )
+    };
+    match mod_slot.source() {
+        ModSource::Off => {
+            Box::new(constant(0.0) >> param_sink(dest, ModTarget::Global, contributions))
+        }
+        ModSource::Lfo1 => voice_slot_output(
+            synth_params,
+            |v| voice_lfo_level(synth_params, v, 0),
+            mod_slot,
+            dest,
+            contributions,
+        ),
+        ModSource::Lfo2 => voice_slot_output(
+            synth_params,
+            |v| voice_lfo_level(synth_params, v, 1),
+            mod_slot,
+            dest,
+            contributions,
+        ),
+        ModSource::Env1 => output(|v| &v.env_levels[0]),
+        ModSource::Env2 => output(|v| &v.env_levels[1]),
+        ModSource::Env3 => output(|v| &v.env_levels[2]),
+        ModSource::Env4 => output(|v| &v.env_levels[3]),
+        ModSource::Velocity => output(|v| &v.velocity),
+        ModSource::Key => voice_slot_output(
+            synth_params,
+            |v| var(&v.note) * (1.0 / 127.0),
+            mod_slot,
+            dest,
+            contributions,
+        ),
+        ModSource::ModWheel => {
+            slot_output(var(&synth_params.mod_wheel), mod_slot, dest, contributions)
+        }
+        ModSource::Aftertouch => output(|v| &v.aftertouch),
+        ModSource::Pressure => output(|v| &v.pressure),
+        ModSource::Slide => output(|v| &v.slide),
+    }
 }
 
-pub fn pitch_bend_factor(bend: u16) -> f32 {
-    2.0_f32.powf(((bend as f32 - 8192.0) / 8192.0) / 12.0)
+/// Pitch factor for a 14 bit bend value, `up` and `down` are the bend ranges in semitones.
+pub fn pitch_bend_factor(bend: u16, up: f32, down: f32) -> f32 {
+    let bend = bend as f32 - 8192.0;
+    let semitones = if bend > 0.0 {
+        bend / 8191.0 * up
+    } else {
+        bend / 8192.0 * down
+    };
+    2.0_f32.powf(semitones / 12.0)
 }
 
 fn run_synth<T: SizedSample + FromSample<f32>>(
@@ -120,3 +401,40 @@ pub fn write_data<T: SizedSample + FromSample<f32>>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poly::MonoPoly;
+
+    /// Peak of the first 100 ms of a Stack voice, `sensitivity` is the velocity sensitivity
+    /// of the carrier.
+    fn carrier_peak(velocity: u8, sensitivity: f32) -> f32 {
+        let synth_params = SynthParams::new(1);
+        *synth_params.algorithm.lock().unwrap() = Algorithm::Stack;
+        synth_params.ops[0].velocity_sens.set_value(sensitivity);
+        let mut mono_poly = MonoPoly::new(1);
+        mono_poly.on_voice_on(60, velocity, &synth_params.voice_params);
+
+        let mut voice = create_sound(&synth_params, 0);
+        voice.set_sample_rate(44100.0);
+        let mut output = [0.0; 2];
+        let mut peak: f32 = 0.0;
+        for _ in 0..4410 {
+            voice.tick(&[], &mut output);
+            peak = peak.max(output[0].abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn velocity_only_reaches_the_level_through_the_sensitivity() {
+        let soft = carrier_peak(1, 0.0);
+        assert!(soft > 0.0);
+        assert_eq!(soft, carrier_peak(127, 0.0));
+
+        // applied once, the carrier level follows velocity linearly
+        let ratio = carrier_peak(1, 1.0) / carrier_peak(127, 1.0);
+        assert!((ratio - 1.0 / 127.0).abs() < 1e-4, "{ratio}");
+    }
+}
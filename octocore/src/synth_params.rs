@@ -1,40 +1,274 @@
-use crate::param::Param;
-use crate::poly::VoiceIndex;
+use crate::adsr::{EnvKind, EnvMode, LoopMode};
+use crate::algorithm::Algorithm;
+use crate::key_scaling::KeyCurve;
+use crate::lfo::{LfoParams, LFOS};
+use crate::modulation::{ModSlot, MOD_SLOTS};
+use crate::p_wave::Waveform;
+use crate::param::{Curve, Param, Smoothing, Unit};
+use crate::poly::{NotePriority, VoiceIndex, VoiceMode};
+use crate::synth::coarse_ratio;
 use fundsp::prelude::shared;
 use fundsp::shared::Shared;
 use std::iter::repeat_with;
+use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
 pub struct AdsrParams {
-    pub a: Shared,
-    pub d: Shared,
-    pub s: Shared,
-    pub r: Shared,
+    /// Segment times in seconds
+    pub a: Param,
+    pub d: Param,
+    pub s: Param,
+    pub r: Param,
+    /// Only used by [`crate::adsr::EnvKind::Dahdsr`]
+    pub delay: Param,
+    pub hold: Param,
+    /// Segment curves in -1..1, see [`crate::adsr::curve`]
+    pub a_curve: Param,
+    pub d_curve: Param,
+    pub r_curve: Param,
+    /// See [`crate::adsr::EnvMode`]
+    pub mode: Param,
+    /// See [`crate::adsr::LoopMode`]
+    pub loop_mode: Param,
+    /// First and last looped segment, counted over the segments of the selected envelope kind
+    pub loop_start: Param,
+    pub loop_end: Param,
 }
+
+/// Longest attack, decay and release in seconds.
+const MAX_SEGMENT_TIME: f32 = 20.0;
+/// Longest delay and hold in seconds.
+const MAX_HOLD_TIME: f32 = 10.0;
+/// Shortest segment, short enough to sound instant while keeping the time curve logarithmic.
+const MIN_SEGMENT_TIME: f32 = 0.001;
+
+fn segment_time(value: f32, max: f32, name: &'static str) -> Param {
+    Param::new(value, (MIN_SEGMENT_TIME, max), None)
+        .with_name(name)
+        .with_unit(Unit::Seconds)
+        .with_curve(Curve::Log)
+}
+
+/// DX rates and levels both run from 0 to 99.
+fn dx_stage(value: f32, name: &'static str) -> Param {
+    Param::new(value, (0.0, 99.0), None)
+        .with_name(name)
+        .with_step(1.0)
+}
+
+fn segment_curve(name: &'static str) -> Param {
+    Param::new(0.0, (-1.0, 1.0), None).with_name(name)
+}
+
 impl Default for AdsrParams {
     fn default() -> Self {
         Self {
-            a: shared(0.01),
-            d: shared(0.0),
-            s: shared(1.0),
-            r: shared(0.0),
+            a: segment_time(0.01, MAX_SEGMENT_TIME, "Attack"),
+            d: segment_time(MIN_SEGMENT_TIME, MAX_SEGMENT_TIME, "Decay"),
+            s: Param::new(1.0, (0.0, 1.0), None)
+                .with_name("Sustain")
+                .with_unit(Unit::Percent),
+            r: segment_time(MIN_SEGMENT_TIME, MAX_SEGMENT_TIME, "Release"),
+            delay: segment_time(MIN_SEGMENT_TIME, MAX_HOLD_TIME, "Delay"),
+            hold: segment_time(MIN_SEGMENT_TIME, MAX_HOLD_TIME, "Hold"),
+            a_curve: segment_curve("A Curve"),
+            d_curve: segment_curve("D Curve"),
+            r_curve: segment_curve("R Curve"),
+            mode: Param::new(0.0, (0.0, 1.0), None)
+                .with_name("Mode")
+                .with_step(1.0)
+                .with_formatter(|value| EnvMode::from_value(value).name().to_string()),
+            loop_mode: Param::new(0.0, (0.0, 2.0), None)
+                .with_name("Loop")
+                .with_step(1.0)
+                .with_formatter(|value| LoopMode::from_value(value).name().to_string()),
+            loop_start: Param::new(0.0, (0.0, 3.0), None)
+                .with_name("Loop Start")
+                .with_step(1.0),
+            loop_end: Param::new(3.0, (0.0, 3.0), None)
+                .with_name("Loop End")
+                .with_step(1.0),
         }
     }
 }
 
+impl AdsrParams {
+    /// Parameters of the amp page in encoder order.
+    pub fn amp_params(&self) -> [&Param; 8] {
+        [
+            &self.a,
+            &self.d,
+            &self.s,
+            &self.r,
+            &self.loop_mode,
+            &self.loop_start,
+            &self.loop_end,
+            &self.mode,
+        ]
+    }
+}
+
+/// DX7 style envelope, rates and levels are in 0..99 as on the DX7, see [`crate::adsr::dx_segment_time`].
+/// R1..R3 move towards L1..L3 while the key is held, R4 releases to L4.
+#[derive(Clone)]
+pub struct DxEnvParams {
+    pub r1: Param,
+    pub r2: Param,
+    pub r3: Param,
+    pub r4: Param,
+    pub l1: Param,
+    pub l2: Param,
+    pub l3: Param,
+    pub l4: Param,
+}
+
+impl Default for DxEnvParams {
+    fn default() -> Self {
+        Self {
+            r1: dx_stage(95.0, "R1"),
+            r2: dx_stage(60.0, "R2"),
+            r3: dx_stage(40.0, "R3"),
+            r4: dx_stage(50.0, "R4"),
+            l1: dx_stage(99.0, "L1"),
+            l2: dx_stage(90.0, "L2"),
+            l3: dx_stage(85.0, "L3"),
+            l4: dx_stage(0.0, "L4"),
+        }
+    }
+}
+
+impl DxEnvParams {
+    /// Every parameter, also the encoder order of the DX page.
+    pub fn params(&self) -> [&Param; 8] {
+        [
+            &self.r1, &self.r2, &self.r3, &self.r4, &self.l1, &self.l2, &self.l3, &self.l4,
+        ]
+    }
+}
+
+/// Whether an operator follows the voice pitch or runs at a fixed frequency.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FreqMode {
+    Ratio,
+    Fixed,
+}
+
+impl FreqMode {
+    pub fn from_value(value: f32) -> Self {
+        if value < 0.5 {
+            FreqMode::Ratio
+        } else {
+            FreqMode::Fixed
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FreqMode::Ratio => "Ratio",
+            FreqMode::Fixed => "Fixed",
+        }
+    }
+}
+
+/// Encoder steps on levels ramp linearly, pitch glides exponentially.
+const LEVEL_SMOOTHING: Smoothing = Smoothing::Linear(0.02);
+const PITCH_SMOOTHING: Smoothing = Smoothing::OnePole(0.01);
+
 #[derive(Clone)]
 pub struct OpParams {
+    /// Coarse ratio, values below 1 map onto 0.5..1
     pub ratio: Param,
+    pub fine: Param,
+    /// Detune in cents
+    pub detune: Param,
+    pub freq_mode: Param,
+    /// Frequency in Hz used in [`FreqMode::Fixed`]
+    pub fixed_freq: Param,
     pub volume: Param,
+    pub feedback: Param,
+    /// Index into [`crate::p_wave::Waveform`]
+    pub waveform: Param,
+    pub velocity_sens: Param,
+    /// MIDI note splitting left and right key scaling
+    pub key_breakpoint: Param,
+    pub key_left_depth: Param,
+    pub key_right_depth: Param,
+    /// Index into [`crate::key_scaling::KeyCurve`]
+    pub key_left_curve: Param,
+    pub key_right_curve: Param,
+    pub key_rate_scaling: Param,
+    /// Index into [`crate::adsr::EnvKind`]
+    pub env_kind: Param,
     pub adsr_params: AdsrParams,
+    pub dx_params: DxEnvParams,
 }
 
 impl Default for OpParams {
     fn default() -> Self {
         Self {
-            ratio: Param::new(1.0, (1.0, 999.0), None),
-            volume: Param::new(0.05, (0.0, 1.0), None),
+            ratio: Param::new(1.0, (0.0, 31.0), None)
+                .with_smoothing(PITCH_SMOOTHING)
+                .with_name("Ratio")
+                .with_step(1.0)
+                .with_formatter(|value| format!("x{:.2}", coarse_ratio(value))),
+            fine: Param::new(0.0, (0.0, 0.99), None)
+                .with_smoothing(PITCH_SMOOTHING)
+                .with_name("Fine"),
+            detune: Param::new(0.0, (-50.0, 50.0), None)
+                .with_smoothing(PITCH_SMOOTHING)
+                .with_name("Detune")
+                .with_unit(Unit::Cents),
+            freq_mode: Param::new(0.0, (0.0, 1.0), None)
+                .with_name("Mode")
+                .with_step(1.0)
+                .with_formatter(|value| FreqMode::from_value(value).name().to_string()),
+            fixed_freq: Param::new(440.0, (1.0, 9999.0), None)
+                .with_smoothing(PITCH_SMOOTHING)
+                .with_name("Fixed")
+                .with_unit(Unit::Hz)
+                .with_curve(Curve::Log),
+            volume: Param::new(0.05, (0.0, 1.0), None)
+                .with_smoothing(LEVEL_SMOOTHING)
+                .with_name("Volume")
+                .with_unit(Unit::Percent)
+                .with_curve(Curve::Skew(2.0)),
+            feedback: Param::new(0.0, (0.0, 1.0), None)
+                .with_smoothing(LEVEL_SMOOTHING)
+                .with_name("Feedback")
+                .with_unit(Unit::Percent),
+            waveform: Param::new(0.0, (0.0, 6.0), None)
+                .with_name("Wave")
+                .with_step(1.0)
+                .with_formatter(|value| Waveform::from_value(value).name().to_string()),
+            velocity_sens: Param::new(0.0, (0.0, 1.0), None)
+                .with_name("Velocity")
+                .with_unit(Unit::Percent),
+            key_breakpoint: Param::new(60.0, (0.0, 127.0), None)
+                .with_name("Break")
+                .with_step(1.0),
+            key_left_depth: Param::new(0.0, (0.0, 1.0), None)
+                .with_name("L Depth")
+                .with_unit(Unit::Percent),
+            key_right_depth: Param::new(0.0, (0.0, 1.0), None)
+                .with_name("R Depth")
+                .with_unit(Unit::Percent),
+            key_left_curve: Param::new(0.0, (0.0, 3.0), None)
+                .with_name("L Curve")
+                .with_step(1.0)
+                .with_formatter(|value| KeyCurve::from_value(value).name().to_string()),
+            key_right_curve: Param::new(0.0, (0.0, 3.0), None)
+                .with_name("R Curve")
+                .with_step(1.0)
+                .with_formatter(|value| KeyCurve::from_value(value).name().to_string()),
+            key_rate_scaling: Param::new(0.0, (0.0, 1.0), None)
+                .with_name("Rate Scl")
+                .with_unit(Unit::Percent),
+            env_kind: Param::new(0.0, (0.0, 2.0), None)
+                .with_name("Envelope")
+                .with_step(1.0)
+                .with_formatter(|value| EnvKind::from_value(value).name().to_string()),
             adsr_params: AdsrParams::default(),
+            dx_params: DxEnvParams::default(),
         }
     }
 }
@@ -42,26 +276,163 @@ impl Default for OpParams {
 #[derive(Clone)]
 pub struct VoiceParams {
     pub pitch: Shared,
-    pub volume: Shared,
+    /// Time in seconds the pitch takes to reach a new note
+    pub glide: Shared,
+    /// Pitch factor of the voice within its unison stack
+    pub detune: Shared,
+    /// Stereo position in -1..1
+    pub pan: Shared,
+    pub note: Shared,
+    /// Note on velocity in 0..1, reaches the level through the velocity sensitivity of
+    /// each operator
+    pub velocity: Shared,
     pub pitch_bend: Shared,
     pub control: Shared,
+    /// Incremented on every note on so envelopes can retrigger while the gate stays open
+    pub trigger: Shared,
+    /// Output level of the voice, written by the audio thread
+    pub level: Shared,
+    /// Polyphonic aftertouch of the played key in 0..1
+    pub aftertouch: Shared,
+    /// Channel pressure in 0..1
+    pub pressure: Shared,
+    /// Pitch factor from the MPE member channel of the note
+    pub channel_bend: Shared,
+    /// MPE slide (CC74) in 0..1
+    pub slide: Shared,
+    /// Envelope level of every operator, written by the audio thread
+    pub env_levels: Vec<Shared>,
+    /// Output of the per voice LFOs, written by the audio thread
+    pub lfo_levels: Vec<Shared>,
 }
 
 impl Default for VoiceParams {
     fn default() -> Self {
         Self {
             pitch: shared(0.0),
-            volume: shared(0.0),
-            pitch_bend: shared(0.0),
+            glide: shared(0.0),
+            detune: shared(1.0),
+            pan: shared(0.0),
+            note: shared(0.0),
+            velocity: shared(0.0),
+            pitch_bend: shared(1.0),
             control: shared(0.0),
+            trigger: shared(0.0),
+            level: shared(0.0),
+            aftertouch: shared(0.0),
+            pressure: shared(0.0),
+            channel_bend: shared(1.0),
+            slide: shared(0.0),
+            env_levels: repeat_with(|| shared(0.0)).take(4).collect(),
+            lfo_levels: repeat_with(|| shared(0.0)).take(LFOS).collect(),
         }
     }
 }
 
+/// Voice allocation settings, applied to [`crate::poly::MonoPoly`] on change.
+#[derive(Clone)]
+pub struct VoiceSettings {
+    pub mode: Arc<Mutex<VoiceMode>>,
+    pub priority: Arc<Mutex<NotePriority>>,
+    /// Portamento time in seconds
+    pub glide: Param,
+    /// Voices started by every note
+    pub unison: Param,
+    /// Detune of the outermost unison voices in cents
+    pub unison_detune: Param,
+    /// Stereo width of the unison voices
+    pub unison_spread: Param,
+    /// Pitch bend ranges in semitones
+    pub bend_up: Param,
+    pub bend_down: Param,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            mode: Arc::new(Mutex::new(VoiceMode::OpenPoly)),
+            priority: Arc::new(Mutex::new(NotePriority::Last)),
+            glide: Param::new(0.0, (0.0, 5.0), None)
+                .with_name("Glide")
+                .with_unit(Unit::Seconds)
+                .with_curve(Curve::Skew(3.0)),
+            unison: Param::new(1.0, (1.0, 8.0), None)
+                .with_name("Unison")
+                .with_step(1.0),
+            unison_detune: Param::new(10.0, (0.0, 100.0), None)
+                .with_name("Detune")
+                .with_unit(Unit::Cents),
+            unison_spread: Param::new(0.5, (0.0, 1.0), None)
+                .with_name("Spread")
+                .with_unit(Unit::Percent),
+            bend_up: Param::new(2.0, (0.0, 24.0), None)
+                .with_name("Bend Up")
+                .with_unit(Unit::Semitones)
+                .with_step(1.0),
+            bend_down: Param::new(2.0, (0.0, 24.0), None)
+                .with_name("Bend Dn")
+                .with_unit(Unit::Semitones)
+                .with_step(1.0),
+        }
+    }
+}
+
+impl OpParams {
+    /// Parameters of the tone page in encoder order.
+    pub fn tone_params(&self) -> [&Param; 8] {
+        [
+            &self.volume,
+            &self.ratio,
+            &self.feedback,
+            &self.fine,
+            &self.detune,
+            &self.freq_mode,
+            &self.fixed_freq,
+            &self.waveform,
+        ]
+    }
+
+    /// Parameters of the envelope page in encoder order.
+    pub fn env_params(&self) -> [&Param; 6] {
+        let adsr = &self.adsr_params;
+        [
+            &self.env_kind,
+            &adsr.delay,
+            &adsr.hold,
+            &adsr.a_curve,
+            &adsr.d_curve,
+            &adsr.r_curve,
+        ]
+    }
+
+    /// Parameters of the key scaling page in encoder order.
+    pub fn scaling_params(&self) -> [&Param; 7] {
+        [
+            &self.velocity_sens,
+            &self.key_breakpoint,
+            &self.key_left_depth,
+            &self.key_right_depth,
+            &self.key_left_curve,
+            &self.key_right_curve,
+            &self.key_rate_scaling,
+        ]
+    }
+}
+
 #[derive(Clone)]
 pub struct SynthParams {
     pub voice_params: Vec<VoiceParams>,
     pub ops: Vec<OpParams>,
+    pub algorithm: Arc<Mutex<Algorithm>>,
+    pub voice_settings: VoiceSettings,
+    pub mod_slots: Vec<ModSlot>,
+    /// Mod wheel (CC1) in 0..1
+    pub mod_wheel: Shared,
+    pub lfos: Vec<LfoParams>,
+    /// Output of the global LFOs, written by the audio thread
+    pub lfo_levels: Vec<Shared>,
+    /// BPM for tempo synced LFOs
+    pub tempo: Param,
 }
 
 impl Default for SynthParams {
@@ -69,6 +440,15 @@ impl Default for SynthParams {
         Self {
             voice_params: repeat_with(|| VoiceParams::default()).take(8).collect(),
             ops: repeat_with(|| OpParams::default()).take(4).collect(),
+            algorithm: Arc::new(Mutex::new(Algorithm::Stack)),
+            voice_settings: VoiceSettings::default(),
+            mod_slots: repeat_with(|| ModSlot::default()).take(MOD_SLOTS).collect(),
+            mod_wheel: shared(0.0),
+            lfos: repeat_with(|| LfoParams::default()).take(LFOS).collect(),
+            lfo_levels: repeat_with(|| shared(0.0)).take(LFOS).collect(),
+            tempo: Param::new(120.0, (20.0, 300.0), None)
+                .with_name("Tempo")
+                .with_step(1.0),
         }
     }
 }
@@ -80,6 +460,19 @@ impl SynthParams {
                 .take(voice_count as usize)
                 .collect(),
             ops: repeat_with(|| OpParams::default()).take(4).collect(),
+            algorithm: Arc::new(Mutex::new(Algorithm::Stack)),
+            voice_settings: VoiceSettings::default(),
+            mod_slots: repeat_with(|| ModSlot::default()).take(MOD_SLOTS).collect(),
+            mod_wheel: shared(0.0),
+            lfos: repeat_with(|| LfoParams::default()).take(LFOS).collect(),
+            lfo_levels: repeat_with(|| shared(0.0)).take(LFOS).collect(),
+            tempo: Param::new(120.0, (20.0, 300.0), None)
+                .with_name("Tempo")
+                .with_step(1.0),
         }
     }
+
+    pub fn algorithm(&self) -> Algorithm {
+        *self.algorithm.lock().unwrap()
+    }
 }
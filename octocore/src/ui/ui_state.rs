@@ -1,28 +1,50 @@
-use crate::modulation::ModDestination;
+use crate::algorithm::Algorithm;
+use crate::mpe::MpeEvent;
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
 pub enum OpPage {
     Tone,
     Amp,
+    Scaling,
+    Env,
+    Dx,
+}
+
+/// Modulation page views, the matrix or the settings of one LFO.
+#[derive(Clone, Copy)]
+pub enum ModPage {
+    Matrix,
+    Lfo(usize),
 }
 
 pub enum Page {
     Op(u8),
     Modulation,
+    Algorithm,
+    Voice,
 }
 
 #[derive(Clone)]
 pub struct UIState {
     pub page: Arc<Mutex<Page>>,
     pub op_subpage: Arc<Mutex<OpPage>>,
-    pub lfo_dest: Arc<Mutex<(usize, ModDestination)>>,
+    pub mod_subpage: Arc<Mutex<ModPage>>,
+    /// Modulation matrix slot edited on the modulation page
+    pub mod_slot: Arc<Mutex<usize>>,
 }
 
 pub enum InputEvent {
     PageChange(Page),
     OpSubpageChange(OpPage),
-    LFO(ModDestination),
+    ModSubpageChange(ModPage),
+    ModSlotChange(usize),
+    AlgorithmChange(Algorithm),
+    VoiceSettingsChange,
     NoteOn { note: u8, velocity: u8 },
     NoteOff { note: u8 },
+    PolyPressure { note: u8, pressure: u8 },
+    Mpe(MpeEvent),
+    Sustain(bool),
+    Sostenuto(bool),
 }